@@ -0,0 +1,53 @@
+use std::{io, time::{Duration, Instant}};
+
+use disassembly::core::{bytecode::{BytecodeConverter, Instruction, Target}, interpreter::{Interpreter, MemoryUnit}, span::Spanned, tokenizer::Tokenizer};
+
+const OUTER: u16 = 200;
+const INNER: u16 = 5000;
+const PADDING: usize = 200;
+const RUNS: u32 = 5;
+
+// The interpreter before labels were linked: every taken jump scans the program for its label.
+fn scan(program: &[Spanned<Instruction>]) {
+  let mut stack: [MemoryUnit; 2] = [0; 2];
+  let mut ptr = 0;
+  let mut ip = 0;
+  while let Some(ins) = program.get(ip) {
+    ip += 1;
+    match &ins.value {
+      Instruction::MoveStack(addr) => ptr = *addr as usize,
+      Instruction::Increment(count) => stack[ptr] = stack[ptr].wrapping_add(*count),
+      Instruction::Decrement(count) => stack[ptr] = stack[ptr].wrapping_sub(*count),
+      Instruction::Label(_) => { },
+      Instruction::JumpNotZero(Target { name, .. }) => if stack[ptr] != 0 {
+        ip = program.iter().position(|ins| matches!(&ins.value, Instruction::Label(label) if label == name)).unwrap();
+      },
+      ins => panic!("the baseline does not support {}", ins)
+    }
+  }
+  assert_eq!(stack, [0, 0]);
+}
+
+fn time(run: impl Fn()) -> Duration {
+  let start = Instant::now();
+  for _ in 0..RUNS {
+    run();
+  }
+  start.elapsed() / RUNS
+}
+
+fn main() {
+  let padding: String = (0..PADDING).map(|i| format!(":pad{} ", i)).collect();
+  let source = format!("{}^1 +{} :outer ^0 +{} :inner - $inner ^1 - $outer", padding, OUTER, INNER);
+  let tokens = Tokenizer::new(source.chars().collect(), "bench").tokenize().unwrap();
+  let program = BytecodeConverter::new(tokens).convert().unwrap();
+  let jumps = OUTER as f64 * INNER as f64;
+
+  let scanned = time(|| scan(&program));
+  let linked = time(|| Interpreter::new(program.clone(), io::empty(), io::sink()).interpret().unwrap());
+  let per_jump = |elapsed: Duration| elapsed.as_nanos() as f64 / jumps;
+  println!("tight loop: {} jumps behind {} labels", jumps, PADDING);
+  println!("  label scan: {:?} ({:.1} ns/iteration)", scanned, per_jump(scanned));
+  println!("  linked:     {:?} ({:.1} ns/iteration)", linked, per_jump(linked));
+  println!("  speedup:    {:.1}x", scanned.as_secs_f64() / linked.as_secs_f64());
+}
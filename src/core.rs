@@ -1,7 +1,11 @@
 pub mod error;
+pub mod diagnostics;
 pub mod processor;
 pub mod tokenizer;
 pub mod bytecode;
+pub mod binary;
+pub mod disassembler;
 pub mod interpreter;
 pub mod generation;
 pub mod parser;
+pub mod span;
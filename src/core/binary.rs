@@ -0,0 +1,322 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::core::{bytecode::{self, Instruction, Target}, error::{BinaryError, DSAsmError}, interpreter::{Machine, MemoryUnit}, span::{Span, Spanned}};
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Image {
+  pub instructions: Vec<Spanned<Instruction>>,
+  pub symbols: HashMap<MemoryUnit, String>,
+  pub machine: Machine,
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  offset: usize,
+  word: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], DSAsmError> {
+    if self.offset + len > self.bytes.len() {
+      return Err(BinaryError::Truncated { offset: self.offset }.into());
+    }
+    let ret = &self.bytes[self.offset..self.offset + len];
+    self.offset += len;
+    Ok(ret)
+  }
+
+  fn remaining(&self) -> usize {
+    self.bytes.len() - self.offset
+  }
+
+  fn u8(&mut self) -> Result<u8, DSAsmError> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16, DSAsmError> {
+    Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+  }
+
+  fn u32(&mut self) -> Result<u32, DSAsmError> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn word(&mut self) -> Result<MemoryUnit, DSAsmError> {
+    let mut buf = [0; size_of::<MemoryUnit>()];
+    buf[..self.word].copy_from_slice(self.take(self.word)?);
+    Ok(MemoryUnit::from_le_bytes(buf))
+  }
+
+  fn string(&mut self) -> Result<String, DSAsmError> {
+    let offset = self.offset;
+    let len = self.u16()? as usize;
+    String::from_utf8(self.take(len)?.to_vec()).map_err(|_| BinaryError::InvalidString { offset }.into())
+  }
+}
+
+fn put_string(out: &mut Vec<u8>, s: &str) {
+  out.extend((s.len() as u16).to_le_bytes());
+  out.extend(s.as_bytes());
+}
+
+impl Image {
+  pub const MAGIC: [u8; 4] = *b"DSB\0";
+  pub const VERSION: u16 = 1;
+  const DEBUG: u8 = 1;
+
+  pub fn new(instructions: Vec<Spanned<Instruction>>, symbols: HashMap<MemoryUnit, String>, machine: Machine) -> Image {
+    Image { instructions, symbols, machine }
+  }
+
+  // Images carry the machine they were built for, so requested settings can only confirm it.
+  pub fn check_machine(&self, bits: Option<u32>, memory: Option<usize>) -> Result<(), DSAsmError> {
+    match (bits, memory) {
+      (Some(bits), _) if bits != self.machine.bits => Err(BinaryError::IncompatibleWordSize { found: self.machine.bits, expected: bits }.into()),
+      (_, Some(memory)) if memory != self.machine.memory => Err(BinaryError::IncompatibleMemorySize { found: self.machine.memory, expected: memory }.into()),
+      _ => Ok(())
+    }
+  }
+
+  fn opcode(ins: &Instruction) -> u8 {
+    match ins {
+      Instruction::MoveStack(_) => 0x01,
+      Instruction::Increment(_) => 0x02,
+      Instruction::Decrement(_) => 0x03,
+      Instruction::UserInput => 0x04,
+      Instruction::Print => 0x05,
+      Instruction::Label(_) => 0x06,
+      Instruction::Jump(_) => 0x07,
+      Instruction::JumpZero(_) => 0x08,
+      Instruction::JumpNotZero(_) => 0x09,
+      Instruction::Invert => 0x0A,
+      Instruction::Multiply(_) => 0x0B,
+      Instruction::Divide(_) => 0x0C,
+      Instruction::Clear => 0x0D,
+      Instruction::Dereference(_) => 0x0E,
+      Instruction::Store(_) => 0x0F,
+      Instruction::Goto(_) => 0x10,
+      Instruction::Call(_) => 0x11,
+      Instruction::Ret => 0x12,
+      Instruction::Compare(_) => 0x13,
+      Instruction::ShiftL(_) => 0x14,
+      Instruction::ShiftR(_) => 0x15,
+      Instruction::Or(_) => 0x16,
+      Instruction::Bounds(..) => 0x17,
+      Instruction::StackLimit(_) => 0x18,
+      Instruction::Invalid => 0x00,
+    }
+  }
+
+  pub fn to_bytes(&self, debug: bool) -> Vec<u8> {
+    let word = self.machine.bytes();
+    let mut labels: Vec<&str> = Vec::new();
+    let mut indices: HashMap<&str, u32> = HashMap::new();
+    for ins in &self.instructions {
+      let name = match &ins.value {
+        Instruction::Label(name) | Instruction::Bounds(name, _) => Some(name),
+        ins => ins.target().map(|target| &target.name)
+      };
+      if let Some(name) = name {
+        indices.entry(name.as_str()).or_insert_with(|| {
+          labels.push(name.as_str());
+          labels.len() as u32 - 1
+        });
+      }
+    }
+    let mut code: Vec<u8> = Vec::new();
+    for ins in &self.instructions {
+      code.push(Image::opcode(&ins.value));
+      match &ins.value {
+        Instruction::MoveStack(w) | Instruction::Increment(w) | Instruction::Decrement(w) |
+        Instruction::Multiply(w) | Instruction::Divide(w) | Instruction::Dereference(w) |
+        Instruction::Store(w) | Instruction::Goto(w) | Instruction::Compare(w) |
+        Instruction::ShiftL(w) | Instruction::ShiftR(w) | Instruction::Or(w) |
+        Instruction::StackLimit(w) => code.extend(&w.to_le_bytes()[..word]),
+        Instruction::Label(name) => code.extend(indices[name.as_str()].to_le_bytes()),
+        Instruction::Jump(target) | Instruction::JumpZero(target) |
+        Instruction::JumpNotZero(target) | Instruction::Call(target) => code.extend(indices[target.name.as_str()].to_le_bytes()),
+        Instruction::Bounds(name, len) => {
+          code.extend(indices[name.as_str()].to_le_bytes());
+          code.extend(&len.to_le_bytes()[..word]);
+        },
+        Instruction::UserInput | Instruction::Print | Instruction::Invert | Instruction::Clear |
+        Instruction::Ret | Instruction::Invalid => { }
+      }
+    }
+
+    let mut out: Vec<u8> = Vec::new();
+    out.extend(Image::MAGIC);
+    out.extend(Image::VERSION.to_le_bytes());
+    out.push(self.machine.bits as u8);
+    out.extend((self.machine.memory as u32).to_le_bytes());
+    out.push(if debug { Image::DEBUG } else { 0 });
+
+    out.extend((labels.len() as u32).to_le_bytes());
+    labels.iter().for_each(|label| put_string(&mut out, label));
+
+    out.extend((self.instructions.len() as u32).to_le_bytes());
+    out.extend(code);
+
+    let mut symbols: Vec<(&MemoryUnit, &String)> = self.symbols.iter().collect();
+    symbols.sort();
+    out.extend((symbols.len() as u32).to_le_bytes());
+    symbols.iter().for_each(|(addr, name)| {
+      out.extend(&addr.to_le_bytes()[..word]);
+      put_string(&mut out, name);
+    });
+
+    if debug {
+      let mut files: Vec<Rc<str>> = Vec::new();
+      self.instructions.iter().for_each(|ins| if !files.contains(&ins.span.file) { files.push(ins.span.file.clone()) });
+      out.extend((files.len() as u32).to_le_bytes());
+      files.iter().for_each(|file| put_string(&mut out, file));
+      self.instructions.iter().for_each(|ins| {
+        let file = files.iter().position(|f| *f == ins.span.file).unwrap() as u32;
+        [file, ins.span.line as u32, ins.span.column as u32, ins.span.len as u32].iter().for_each(|v| out.extend(v.to_le_bytes()));
+      });
+    }
+    out
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Image, DSAsmError> {
+    let mut reader = Reader { bytes, offset: 0, word: 0 };
+    if reader.take(4).ok() != Some(Image::MAGIC.as_slice()) {
+      return Err(BinaryError::BadMagic.into());
+    }
+    let version = reader.u16()?;
+    if version != Image::VERSION {
+      return Err(BinaryError::UnsupportedVersion { found: version, expected: Image::VERSION }.into());
+    }
+    let machine = Machine::new(reader.u8()? as u32, reader.u32()? as usize);
+    if !machine.has_valid_bits() {
+      return Err(BinaryError::UnsupportedWordSize { found: machine.bits }.into());
+    }
+    if !machine.has_valid_memory() {
+      return Err(BinaryError::UnsupportedMemorySize { found: machine.memory, bits: machine.bits }.into());
+    }
+    reader.word = machine.bytes();
+    let flags = reader.u8()?;
+
+    let labels = (0..reader.u32()?).map(|_| reader.string()).collect::<Result<Vec<String>, DSAsmError>>()?;
+
+    let count = reader.u32()? as usize;
+    let mut instructions: Vec<Spanned<Instruction>> = Vec::with_capacity(count.min(reader.remaining()));
+    for _ in 0..count {
+      let offset = reader.offset;
+      let opcode = reader.u8()?;
+      let label = |reader: &mut Reader| -> Result<String, DSAsmError> {
+        let index = reader.u32()?;
+        labels.get(index as usize).cloned().ok_or(BinaryError::InvalidLabel { index, offset }.into())
+      };
+      let ins = match opcode {
+        0x01 => Instruction::MoveStack(reader.word()?),
+        0x02 => Instruction::Increment(reader.word()?),
+        0x03 => Instruction::Decrement(reader.word()?),
+        0x04 => Instruction::UserInput,
+        0x05 => Instruction::Print,
+        0x06 => Instruction::Label(label(&mut reader)?),
+        0x07 => Instruction::Jump(Target::new(label(&mut reader)?)),
+        0x08 => Instruction::JumpZero(Target::new(label(&mut reader)?)),
+        0x09 => Instruction::JumpNotZero(Target::new(label(&mut reader)?)),
+        0x0A => Instruction::Invert,
+        0x0B => Instruction::Multiply(reader.word()?),
+        0x0C => Instruction::Divide(reader.word()?),
+        0x0D => Instruction::Clear,
+        0x0E => Instruction::Dereference(reader.word()?),
+        0x0F => Instruction::Store(reader.word()?),
+        0x10 => Instruction::Goto(reader.word()?),
+        0x11 => Instruction::Call(Target::new(label(&mut reader)?)),
+        0x12 => Instruction::Ret,
+        0x13 => Instruction::Compare(reader.word()?),
+        0x14 => Instruction::ShiftL(reader.word()?),
+        0x15 => Instruction::ShiftR(reader.word()?),
+        0x16 => Instruction::Or(reader.word()?),
+        0x17 => Instruction::Bounds(label(&mut reader)?, reader.word()?),
+        0x18 => Instruction::StackLimit(reader.word()?),
+        opcode => return Err(BinaryError::InvalidOpcode { opcode, offset }.into())
+      };
+      instructions.push(Spanned::new(ins, Span::default()));
+    }
+
+    let mut symbols: HashMap<MemoryUnit, String> = HashMap::new();
+    for _ in 0..reader.u32()? {
+      let addr = reader.word()?;
+      symbols.insert(addr, reader.string()?);
+    }
+
+    if flags & Image::DEBUG != 0 {
+      let files = (0..reader.u32()?).map(|_| reader.string().map(Rc::<str>::from)).collect::<Result<Vec<Rc<str>>, DSAsmError>>()?;
+      for ins in instructions.iter_mut() {
+        let offset = reader.offset;
+        let file = reader.u32()?;
+        let file = files.get(file as usize).cloned().ok_or(BinaryError::InvalidFile { index: file, offset })?;
+        ins.span = Span::new(file, reader.u32()? as usize, reader.u32()? as usize, reader.u32()? as usize);
+      }
+    }
+
+    bytecode::link(&mut instructions)?;
+    Ok(Image { instructions, symbols, machine })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use crate::core::{binary::Image, bytecode::BytecodeConverter, error::{BinaryError, DSAsmError}, interpreter::Machine, span::Span, tokenizer::Tokenizer};
+
+  fn image(machine: Machine) -> Image {
+    let tokens = Tokenizer::new(":start ^3 +200 . ?end [4] =[5] %arr[2] |150 @start\n:end ;".chars().collect(), "test.dsa").with_machine(machine).tokenize().unwrap();
+    let instructions = BytecodeConverter::new(tokens).convert().unwrap();
+    Image::new(instructions, HashMap::from([(3, "x".to_string()), (4, "arr[0]".to_string())]), machine)
+  }
+
+  #[test]
+  fn images_round_trip_with_debug_info() {
+    for bits in Machine::WORD_SIZES {
+      let original = image(Machine::new(bits, 200));
+      assert_eq!(Image::from_bytes(&original.to_bytes(true)).unwrap(), original);
+    }
+  }
+
+  #[test]
+  fn stripped_images_round_trip_without_spans() {
+    let original = image(Machine::default());
+    let loaded = Image::from_bytes(&original.to_bytes(false)).unwrap();
+    assert!(loaded.instructions.iter().all(|ins| ins.span == Span::default()));
+    assert_eq!(loaded.instructions.iter().map(|ins| &ins.value).collect::<Vec<_>>(), original.instructions.iter().map(|ins| &ins.value).collect::<Vec<_>>());
+    assert_eq!((loaded.symbols, loaded.machine), (original.symbols, original.machine));
+  }
+
+  #[test]
+  fn bad_magic_is_rejected() {
+    let mut bytes = image(Machine::default()).to_bytes(false);
+    bytes[0] = b'X';
+    assert!(matches!(Image::from_bytes(&bytes), Err(DSAsmError::Binary(BinaryError::BadMagic))));
+  }
+
+  #[test]
+  fn unsupported_versions_are_rejected() {
+    let mut bytes = image(Machine::default()).to_bytes(false);
+    bytes[4..6].copy_from_slice(&(Image::VERSION + 1).to_le_bytes());
+    assert!(matches!(Image::from_bytes(&bytes), Err(DSAsmError::Binary(BinaryError::UnsupportedVersion { found, expected: Image::VERSION })) if found == Image::VERSION + 1));
+  }
+
+  #[test]
+  fn requested_machines_must_match_the_image() {
+    let image = image(Machine::new(16, 512));
+    assert!(image.check_machine(None, None).is_ok());
+    assert!(image.check_machine(Some(16), Some(512)).is_ok());
+    assert!(matches!(image.check_machine(Some(8), None), Err(DSAsmError::Binary(BinaryError::IncompatibleWordSize { found: 16, expected: 8 }))));
+    assert!(matches!(image.check_machine(None, Some(1024)), Err(DSAsmError::Binary(BinaryError::IncompatibleMemorySize { found: 512, expected: 1024 }))));
+  }
+
+  #[test]
+  fn huge_instruction_counts_are_reported_as_truncated() {
+    let mut bytes = Image::new(Vec::new(), Default::default(), Machine::default()).to_bytes(false);
+    let count = bytes.len() - 8;
+    bytes[count..count + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    bytes.truncate(count + 4);
+    assert!(matches!(Image::from_bytes(&bytes), Err(DSAsmError::Binary(BinaryError::Truncated { .. }))));
+  }
+}
@@ -1,6 +1,18 @@
-use std::{fmt::Display};
+use std::{collections::HashMap, fmt::Display};
 
-use crate::core::{error::DSAsmError, interpreter::MemoryUnit, processor::Processor, tokenizer::Token};
+use crate::core::{error::{ConverterError, DSAsmError}, interpreter::MemoryUnit, processor::Processor, span::Spanned, tokenizer::Token};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
+pub struct Target {
+  pub name: String,
+  pub ip: usize,
+}
+
+impl Target {
+  pub fn new(name: String) -> Target {
+    Target { name, ip: 0 }
+  }
+}
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
 pub enum Instruction {
@@ -10,23 +22,109 @@ pub enum Instruction {
   UserInput,
   Print,
   Label(String),
-  Jump(String),
-  JumpZero(String),
-  JumpNotZero(String),
+  Jump(Target),
+  JumpZero(Target),
+  JumpNotZero(Target),
   Invert,
   Multiply(MemoryUnit),
   Divide(MemoryUnit),
   Clear,
   Dereference(MemoryUnit),
+  Store(MemoryUnit),
   Goto(MemoryUnit),
+  Call(Target),
+  Ret,
   Compare(MemoryUnit),
   ShiftL(MemoryUnit),
   ShiftR(MemoryUnit),
   Or(MemoryUnit),
+  Bounds(String, MemoryUnit),
+  StackLimit(MemoryUnit),
   #[default]
   Invalid
 }
 
+impl Instruction {
+  pub fn target(&self) -> Option<&Target> {
+    match self {
+      Instruction::Jump(target) | Instruction::JumpZero(target) | Instruction::JumpNotZero(target) | Instruction::Call(target) => Some(target),
+      _ => None
+    }
+  }
+
+  pub fn target_mut(&mut self) -> Option<&mut Target> {
+    match self {
+      Instruction::Jump(target) | Instruction::JumpZero(target) | Instruction::JumpNotZero(target) | Instruction::Call(target) => Some(target),
+      _ => None
+    }
+  }
+
+  pub fn to_asm(&self) -> String {
+    match self {
+      Instruction::MoveStack(addr) => format!("^{}", addr),
+      Instruction::Increment(count) => format!("+{}", count),
+      Instruction::Decrement(count) => format!("-{}", count),
+      Instruction::UserInput => ",".to_string(),
+      Instruction::Print => ".".to_string(),
+      Instruction::Label(name) => format!(":{}", name),
+      Instruction::Jump(target) => format!("@{}", target.name),
+      Instruction::JumpZero(target) => format!("?{}", target.name),
+      Instruction::JumpNotZero(target) => format!("${}", target.name),
+      Instruction::Invert => "!".to_string(),
+      Instruction::Multiply(addr) => format!("*{}", addr),
+      Instruction::Divide(addr) => format!("/{}", addr),
+      Instruction::Clear => "~".to_string(),
+      Instruction::Dereference(addr) => format!("[{}]", addr),
+      Instruction::Store(addr) => format!("=[{}]", addr),
+      Instruction::Goto(ip) => format!("->{}", ip),
+      Instruction::Call(target) => format!("&{}", target.name),
+      Instruction::Ret => ";".to_string(),
+      Instruction::Compare(addr) => format!("'{}", addr),
+      Instruction::ShiftL(addr) => format!("<<{}", addr),
+      Instruction::ShiftR(addr) => format!(">>{}", addr),
+      Instruction::Or(addr) => format!("§{}", addr),
+      Instruction::Bounds(name, len) => format!("%{}[{}]", name, len),
+      Instruction::StackLimit(limit) => format!("|{}", limit),
+      Instruction::Invalid => "# Invalid".to_string(),
+    }
+  }
+
+  pub fn to_asm_line(&self) -> String {
+    match self {
+      Instruction::Label(_) => self.to_asm(),
+      other => format!("  {}", other.to_asm())
+    }
+  }
+}
+
+pub fn link(program: &mut [Spanned<Instruction>]) -> Result<(), DSAsmError> {
+  let mut labels: HashMap<String, usize> = HashMap::new();
+  for (ip, ins) in program.iter().enumerate() {
+    if let Instruction::Label(name) = &ins.value {
+      if labels.contains_key(name) {
+        return Err(ConverterError::DuplicateLabel { name: name.clone(), span: ins.span.clone() }.into());
+      }
+      labels.insert(name.clone(), ip);
+    }
+  }
+  for ins in program.iter_mut() {
+    if let Some(target) = ins.value.target_mut() {
+      match labels.get(&target.name) {
+        Some(ip) => target.ip = *ip,
+        None => return Err(ConverterError::UndefinedLabel { name: target.name.clone(), span: ins.span.clone() }.into())
+      }
+    }
+  }
+  Ok(())
+}
+
+pub fn to_asm(program: &[Spanned<Instruction>]) -> Result<String, DSAsmError> {
+  program.iter().enumerate().map(|(ip, ins)| match ins.value {
+    Instruction::Invalid => Err(ConverterError::InvalidInstruction { ip, span: ins.span.clone() }.into()),
+    _ => Ok(ins.value.to_asm_line() + "\n")
+  }).collect()
+}
+
 impl Display for Instruction {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     write!(f, "{:?}", self)
@@ -38,100 +136,152 @@ pub struct BytecodeConverter {
 }
 
 impl BytecodeConverter {
-  pub fn new(i: Vec<Token>) -> BytecodeConverter {
-    BytecodeConverter { base: Processor::new(i) }
+  pub fn new(i: Vec<Spanned<Token>>) -> BytecodeConverter {
+    BytecodeConverter { base: Processor::spanned(i) }
   }
 
   fn get_identifier(&mut self) -> Result<String, DSAsmError> {
-    match self.base.consume() {
-      Token::Identifier(id) => Ok(id),
-      t => {
-        Err(DSAsmError::ConverterError(format!("Expected Identifier instead of '{}'", t)).into())
-      }
+    match self.base.peek() {
+      Token::Identifier(id) => {
+        self.base.consume();
+        Ok(id)
+      },
+      _ => Err(ConverterError::Expected(self.base.expected("identifier")).into())
     }
   }
 
   fn get_literal(&mut self) -> Result<MemoryUnit, DSAsmError> {
-    match self.base.consume() {
-      Token::Literal(lit) => Ok(lit),
-      t => {
-        Err(DSAsmError::ConverterError(format!("Expected literal instead of '{}'", t)).into())
+    match self.base.peek() {
+      Token::Literal(lit) => {
+        self.base.consume();
+        Ok(lit)
+      },
+      _ => Err(ConverterError::Expected(self.base.expected("literal")).into())
+    }
+  }
+
+  fn get_count(&mut self, repeat: Token) -> MemoryUnit {
+    if let Token::Literal(count) = self.base.peek() {
+      self.base.consume();
+      return count;
+    }
+    let mut count: MemoryUnit = 1;
+    loop {
+      let old = self.base.get_peek();
+      if !self.base.tryconsume(repeat.clone()) {
+        break;
+      }
+      if matches!(self.base.peek(), Token::Literal(_)) || (repeat == Token::Minus && self.base.peek_equal(Token::RightAngle)) {
+        self.base.set_peek(old);
+        break;
       }
+      count = count.wrapping_add(1);
     }
+    count
   }
 
-  pub fn convert(&mut self) -> Result<Vec<Instruction>, DSAsmError> {
-    let mut output: Vec<Instruction> = Vec::new();
+  pub fn convert(&mut self) -> Result<Vec<Spanned<Instruction>>, DSAsmError> {
+    let mut output: Vec<Spanned<Instruction>> = Vec::new();
     self.base.set_peek(0);
     while self.base.has_peek() {
+      let start = self.base.span();
+      let old = self.base.get_peek();
       let ins: Instruction = match self.base.consume() {
-        Token::Caret => {
-          match self.base.consume() {
-            Token::Literal(lit) => {
-              Instruction::MoveStack(lit)
-            },
-            t => {
-              return Err(DSAsmError::ConverterError(format!("Expected literal instead of {}", t)).into())
-            }
-          }
-        },
-        Token::Plus => {
-          let mut count: MemoryUnit = 1;
-          while self.base.tryconsume(Token::Plus) {
-            count += 1;
-          };
-          Instruction::Increment(count)
-        },
-        Token::Minus if self.base.tryconsume(Token::RightAngle) => {
-          match self.base.consume() {
-            Token::Literal(lit) => {
-              Instruction::Goto(lit)
-            },
-            t => {
-              return Err(DSAsmError::ConverterError(format!("Expected literal instead of {}", t)).into())
-            }
-          }
-        },
-        Token::Minus => {
-          let mut count: MemoryUnit = 1;
-          while self.base.tryconsume(Token::Minus) {
-            count += 1;
-          };
-          Instruction::Decrement(count)
-        },
+        Token::Caret => Instruction::MoveStack(self.get_literal()?),
+        Token::Plus => Instruction::Increment(self.get_count(Token::Plus)),
+        Token::Minus if self.base.tryconsume(Token::RightAngle) => Instruction::Goto(self.get_literal()?),
+        Token::Minus => Instruction::Decrement(self.get_count(Token::Minus)),
         Token::Comma => Instruction::UserInput,
         Token::Dot => Instruction::Print,
         Token::LabelDef => {
           Instruction::Label(self.get_identifier()?)
         },
         Token::Jmp => {
-          Instruction::Jump(self.get_identifier()?)
+          Instruction::Jump(Target::new(self.get_identifier()?))
         },
         Token::Jze => {
-          Instruction::JumpZero(self.get_identifier()?)
+          Instruction::JumpZero(Target::new(self.get_identifier()?))
         },
         Token::Jnze => {
-          Instruction::JumpNotZero(self.get_identifier()?)
+          Instruction::JumpNotZero(Target::new(self.get_identifier()?))
         },
+        Token::Ampersand => {
+          Instruction::Call(Target::new(self.get_identifier()?))
+        },
+        Token::Semicolon => Instruction::Ret,
         Token::Exclamation => Instruction::Invert,
         Token::Star => Instruction::Multiply(self.get_literal()?),
         Token::Slash => Instruction::Divide(self.get_literal()?),
         Token::Tilde => Instruction::Clear,
         Token::OpenSquare => {
           let val = self.get_literal()?;
-          self.base.require(Token::CloseSquare).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ConverterError(format!("{}", e))))?;
+          self.base.require(Token::CloseSquare).map_err(ConverterError::Expected)?;
           Instruction::Dereference(val)
         },
+        Token::Equals if self.base.tryconsume(Token::OpenSquare) => {
+          let val = self.get_literal()?;
+          self.base.require(Token::CloseSquare).map_err(ConverterError::Expected)?;
+          Instruction::Store(val)
+        },
         Token::Apostrophe => Instruction::Compare(self.get_literal()?),
         Token::LeftAngle if self.base.tryconsume(Token::LeftAngle) => Instruction::ShiftL(self.get_literal()?),
         Token::RightAngle if self.base.tryconsume(Token::RightAngle) => Instruction::ShiftR(self.get_literal()?),
         Token::Or => Instruction::Or(self.get_literal()?),
-        t => {
-          return Err(DSAsmError::ConverterError(format!("Unexpected Token '{}'", t)).into());
+        Token::Pipe => Instruction::StackLimit(self.get_literal()?),
+        Token::Percent => {
+          let name = self.get_identifier()?;
+          self.base.require(Token::OpenSquare).map_err(ConverterError::Expected)?;
+          let len = self.get_literal()?;
+          self.base.require(Token::CloseSquare).map_err(ConverterError::Expected)?;
+          Instruction::Bounds(name, len)
+        },
+        _ => {
+          self.base.set_peek(old);
+          return Err(ConverterError::Expected(self.base.expected("instruction")).into());
         }
       };
-      output.push(ins);
+      output.push(Spanned::new(ins, start.to(&self.base.prev_span())));
     }
+    link(&mut output)?;
     Ok(output)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::core::{bytecode::{self, BytecodeConverter, Instruction, Target}, error::{ConverterError, DSAsmError}, span::{Span, Spanned}, tokenizer::Tokenizer};
+
+  fn program(instructions: Vec<Instruction>) -> Vec<Spanned<Instruction>> {
+    instructions.into_iter().map(|ins| Spanned::new(ins, Span::default())).collect()
+  }
+
+  #[test]
+  fn linking_resolves_targets_to_their_labels() {
+    let mut program = program(vec![Instruction::Jump(Target::new("end".to_string())), Instruction::Print, Instruction::Label("end".to_string())]);
+    bytecode::link(&mut program).unwrap();
+    assert_eq!(program[0].value.target().map(|target| target.ip), Some(2));
+  }
+
+  #[test]
+  fn linking_rejects_undefined_labels() {
+    let mut program = program(vec![Instruction::Label("start".to_string()), Instruction::JumpZero(Target::new("missing".to_string()))]);
+    let result = bytecode::link(&mut program);
+    assert!(matches!(result, Err(DSAsmError::Converter(ConverterError::UndefinedLabel { ref name, .. })) if name == "missing"), "{:?}", result);
+  }
+
+  #[test]
+  fn linking_rejects_duplicate_labels() {
+    let mut program = program(vec![Instruction::Label("twice".to_string()), Instruction::Print, Instruction::Label("twice".to_string())]);
+    let result = bytecode::link(&mut program);
+    assert!(matches!(result, Err(DSAsmError::Converter(ConverterError::DuplicateLabel { ref name, .. })) if name == "twice"), "{:?}", result);
+  }
+
+  #[test]
+  fn adjacent_single_steps_stay_separate() {
+    let program = program(vec![Instruction::Increment(1), Instruction::Increment(1), Instruction::Decrement(1), Instruction::Decrement(1)]);
+    let asm = bytecode::to_asm(&program).unwrap();
+    let tokens = Tokenizer::new(asm.chars().collect(), "test").tokenize().unwrap();
+    let converted: Vec<Instruction> = BytecodeConverter::new(tokens).convert().unwrap().into_iter().map(|ins| ins.value).collect();
+    assert_eq!(converted, program.into_iter().map(|ins| ins.value).collect::<Vec<Instruction>>());
+  }
+}
@@ -0,0 +1,126 @@
+use std::io::IsTerminal;
+
+use colored::{ColoredString, Colorize};
+
+use crate::core::{error::DSAsmError, span::Span};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  pub code: &'static str,
+  pub message: String,
+  pub span: Option<Span>,
+  pub help: Vec<String>,
+}
+
+impl Diagnostic {
+  pub fn new(code: &'static str, message: impl Into<String>) -> Diagnostic {
+    Diagnostic { code, message: message.into(), span: None, help: Vec::new() }
+  }
+
+  pub fn with_span(mut self, span: Span) -> Diagnostic {
+    self.span = Some(span);
+    self
+  }
+
+  pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+    self.help.push(help.into());
+    self
+  }
+
+  fn paint(text: &str, color: bool, style: fn(&str) -> ColoredString) -> String {
+    if color {
+      style(text).to_string()
+    } else {
+      text.to_string()
+    }
+  }
+
+  pub fn render(&self, source: &str, color: bool) -> String {
+    let mut out = format!("{}{}",
+      Diagnostic::paint(&format!("error[{}]", self.code), color, |s| s.red().bold()),
+      Diagnostic::paint(&format!(": {}", self.message), color, |s| s.bold())
+    );
+
+    let line = self.span.as_ref().and_then(|span| span.line.checked_sub(1).and_then(|l| source.lines().nth(l)).map(|text| (span, text)));
+    let gutter = self.span.as_ref().map(|span| span.line.to_string().len()).unwrap_or(1);
+    let pipe = Diagnostic::paint("|", color, |s| s.blue().bold());
+    let pad = " ".repeat(gutter);
+
+    if let Some(span) = &self.span {
+      out += &format!("\n{}{} {}", pad, Diagnostic::paint("-->", color, |s| s.blue().bold()), span);
+    }
+    if let Some((span, text)) = line {
+      let prefix: String = text.chars().take(span.column.saturating_sub(1)).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+      let carets = Diagnostic::paint(&"^".repeat(span.len.max(1)), color, |s| s.red().bold());
+      out += &format!("\n{} {}", pad, pipe);
+      out += &format!("\n{} {} {}", Diagnostic::paint(&span.line.to_string(), color, |s| s.blue().bold()), pipe, text);
+      out += &format!("\n{} {} {}{}", pad, pipe, prefix, carets);
+    }
+    for help in &self.help {
+      out += &format!("\n{} {} {}: {}", pad, Diagnostic::paint("=", color, |s| s.blue().bold()), Diagnostic::paint("help", color, |s| s.bold()), help);
+    }
+    out
+  }
+
+  pub fn emit(&self, source: &str) {
+    eprintln!("{}", self.render(source, std::io::stderr().is_terminal()));
+  }
+}
+
+pub fn report(error: &DSAsmError, source: &str) {
+  error.errors().iter().for_each(|e| Diagnostic::from(*e).emit(source));
+  if let DSAsmError::Multiple { .. } = error {
+    let color = std::io::stderr().is_terminal();
+    eprintln!("{}{}", Diagnostic::paint("error", color, |s| s.red().bold()), Diagnostic::paint(&format!(": {}", error), color, |s| s.bold()));
+  }
+}
+
+impl From<&DSAsmError> for Diagnostic {
+  fn from(value: &DSAsmError) -> Self {
+    let mut diagnostic = Diagnostic::new(value.code(), value.to_string());
+    if let Some(span) = value.span().filter(|span| span.is_known()) {
+      diagnostic = diagnostic.with_span(span.clone());
+    }
+    if let Some(help) = value.help() {
+      diagnostic = diagnostic.with_help(help);
+    }
+    diagnostic
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::core::{diagnostics::Diagnostic, error::{DSAsmError, InterpreterError, ParserError}, span::Span};
+
+  #[test]
+  fn spanned_errors_point_at_the_source() {
+    let source = "let a = 1;\nlet b = c + a;\n";
+    let error = DSAsmError::from(ParserError::UndefinedVariable { name: "c".to_string(), span: Span::new("main.ds".into(), 2, 9, 1) });
+    let rendered = Diagnostic::from(&error).render(source, false);
+    let lines: Vec<&str> = rendered.lines().collect();
+    assert_eq!(lines[0], format!("error[{}]: {}", error.code(), error));
+    assert!(lines[0].starts_with("error[E0202]: "), "{}", lines[0]);
+    assert_eq!(lines[1], " --> main.ds:2:9");
+    assert_eq!(lines[2], "  |");
+    assert_eq!(lines[3], "2 | let b = c + a;");
+    assert_eq!(lines[4], "  |         ^");
+  }
+
+  #[test]
+  fn carets_cover_the_whole_span_and_help_follows() {
+    let error = DSAsmError::from(InterpreterError::IndexOutOfBounds { name: "arr".to_string(), index: 4, len: 2, ip: 7, span: Span::new("main.ds".into(), 1, 3, 6) });
+    let rendered = Diagnostic::from(&error).render("  arr[i] = 1;", false);
+    let lines: Vec<&str> = rendered.lines().collect();
+    assert_eq!(lines[3], "1 |   arr[i] = 1;");
+    assert_eq!(lines[4], "  |   ^^^^^^");
+    assert_eq!(lines[5], "  = help: valid indices are 0 to 1");
+  }
+
+  #[test]
+  fn unknown_locations_are_left_out() {
+    let error = DSAsmError::from(InterpreterError::ReturnStackUnderflow { ip: 3, span: Span::default() });
+    let rendered = Diagnostic::from(&error).render("", false);
+    assert!(!rendered.contains("-->"), "{}", rendered);
+    assert!(!rendered.contains('|'), "{}", rendered);
+  }
+}
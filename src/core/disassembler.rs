@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use crate::core::{bytecode::Instruction, error::{ConverterError, DSAsmError}, interpreter::MemoryUnit, span::Span};
+
+pub struct Disassembler {
+  program: Vec<Instruction>,
+  labels: HashMap<String, usize>,
+  symbols: HashMap<MemoryUnit, String>,
+}
+
+impl Disassembler {
+  const COLUMN: usize = 28;
+
+  pub fn new(program: Vec<Instruction>) -> Disassembler {
+    let labels = program.iter().enumerate().filter_map(|(ip, ins)| match ins {
+      Instruction::Label(name) => Some((name.clone(), ip)),
+      _ => None
+    }).collect();
+    Disassembler { program, labels, symbols: HashMap::new() }
+  }
+
+  pub fn with_symbols(mut self, symbols: HashMap<MemoryUnit, String>) -> Disassembler {
+    self.symbols = symbols;
+    self
+  }
+
+  fn annotation(&self, ins: &Instruction) -> Option<String> {
+    match ins {
+      Instruction::Jump(target) | Instruction::JumpZero(target) | Instruction::JumpNotZero(target) | Instruction::Call(target) => {
+        Some(match self.labels.get(&target.name) {
+          Some(ip) => format!("-> {:04}", ip),
+          None => "-> undefined".to_string()
+        })
+      },
+      Instruction::Goto(ip) => self.labels.iter().find(|(_, target)| **target == *ip as usize).map(|(name, _)| format!("-> {}", name)),
+      Instruction::MoveStack(addr) |
+      Instruction::Multiply(addr) |
+      Instruction::Divide(addr) |
+      Instruction::Dereference(addr) |
+      Instruction::Store(addr) |
+      Instruction::Compare(addr) |
+      Instruction::ShiftL(addr) |
+      Instruction::ShiftR(addr) |
+      Instruction::Or(addr) => self.symbols.get(addr).cloned(),
+      _ => None
+    }
+  }
+
+  pub fn disassemble(&self) -> Result<String, DSAsmError> {
+    self.program.iter().enumerate().map(|(ip, ins)| {
+      if *ins == Instruction::Invalid {
+        return Err(ConverterError::InvalidInstruction { ip, span: Span::default() }.into());
+      }
+      let text = ins.to_asm_line();
+      let comment = match self.annotation(ins) {
+        Some(note) => format!("# {:04}  {}", ip, note),
+        None => format!("# {:04}", ip)
+      };
+      Ok(format!("{:width$}{}\n", text, comment, width = Disassembler::COLUMN))
+    }).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use crate::core::{bytecode::{self, BytecodeConverter, Instruction, Target}, disassembler::Disassembler, error::{ConverterError, DSAsmError}, span::{Span, Spanned}, tokenizer::Tokenizer};
+
+  #[test]
+  fn disassembly_reads_back_as_the_original_program() {
+    let mut program: Vec<Spanned<Instruction>> = [
+      Instruction::Label("start".to_string()),
+      Instruction::MoveStack(3),
+      Instruction::Increment(1),
+      Instruction::Increment(1),
+      Instruction::Increment(7),
+      Instruction::Decrement(1),
+      Instruction::Decrement(12),
+      Instruction::UserInput,
+      Instruction::Print,
+      Instruction::JumpZero(Target::new("end".to_string())),
+      Instruction::JumpNotZero(Target::new("start".to_string())),
+      Instruction::Invert,
+      Instruction::Multiply(4),
+      Instruction::Divide(5),
+      Instruction::Clear,
+      Instruction::Dereference(6),
+      Instruction::Store(7),
+      Instruction::Goto(0),
+      Instruction::Call(Target::new("end".to_string())),
+      Instruction::Compare(8),
+      Instruction::ShiftL(9),
+      Instruction::ShiftR(10),
+      Instruction::Or(11),
+      Instruction::Bounds("arr".to_string(), 4),
+      Instruction::StackLimit(90),
+      Instruction::Jump(Target::new("start".to_string())),
+      Instruction::Label("end".to_string()),
+      Instruction::Ret,
+    ].into_iter().map(|ins| Spanned::new(ins, Span::default())).collect();
+    bytecode::link(&mut program).unwrap();
+    let original: Vec<Instruction> = program.into_iter().map(|ins| ins.value).collect();
+
+    let symbols = HashMap::from([(3, "x".to_string()), (8, "arr[1]".to_string())]);
+    let text = Disassembler::new(original.clone()).with_symbols(symbols).disassemble().unwrap();
+    let tokens = Tokenizer::new(text.chars().collect(), "test").tokenize().unwrap();
+    let converted: Vec<Instruction> = BytecodeConverter::new(tokens).convert().unwrap().into_iter().map(|ins| ins.value).collect();
+    assert_eq!(converted, original);
+  }
+
+  #[test]
+  fn invalid_instructions_cannot_be_disassembled() {
+    let result = Disassembler::new(vec![Instruction::Print, Instruction::Invalid]).disassemble();
+    assert!(matches!(result, Err(DSAsmError::Converter(ConverterError::InvalidInstruction { ip: 1, .. }))));
+  }
+}
@@ -1,38 +1,623 @@
-use std::fmt::Display;
+use std::{error::Error, fmt::Display, io::ErrorKind, num::ParseIntError};
+
+use crate::core::{bytecode::Instruction, interpreter::{Budget, Machine, MemoryUnit}, span::Span};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedError {
+  pub expected: String,
+  pub found: String,
+  pub span: Span,
+}
+
+impl Display for ExpectedError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Expected {}, found {}", self.expected, self.found)
+  }
+}
+
+impl Error for ExpectedError { }
+
+#[derive(Debug)]
+pub enum TokenizerError {
+  InvalidCharacter { ch: char, span: Span },
+  InvalidLiteral { literal: String, span: Span, source: ParseIntError },
+  LiteralOverflow { literal: String, bits: u32, span: Span },
+  InvalidEscape { sequence: String, span: Span },
+  UnterminatedString { span: Span },
+  CharOutOfRange { ch: char, span: Span },
+}
+
+impl TokenizerError {
+  pub fn span(&self) -> &Span {
+    match self {
+      Self::InvalidCharacter { span, .. } |
+      Self::InvalidLiteral { span, .. } |
+      Self::LiteralOverflow { span, .. } |
+      Self::InvalidEscape { span, .. } |
+      Self::UnterminatedString { span } |
+      Self::CharOutOfRange { span, .. } => span
+    }
+  }
+}
+
+impl Display for TokenizerError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::InvalidCharacter { ch, .. } => write!(f, "Invalid character '{}'", ch),
+      Self::InvalidLiteral { literal, .. } => write!(f, "Invalid literal '{}'", literal),
+      Self::LiteralOverflow { literal, bits, .. } => write!(f, "Literal '{}' does not fit in a {}-bit word", literal, bits),
+      Self::InvalidEscape { sequence, .. } => write!(f, "Invalid escape sequence '{}'", sequence),
+      Self::UnterminatedString { .. } => write!(f, "Unterminated string literal"),
+      Self::CharOutOfRange { ch, .. } => write!(f, "Character '{}' does not fit in a memory cell", ch),
+    }
+  }
+}
+
+impl Error for TokenizerError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      Self::InvalidLiteral { source, .. } => Some(source),
+      _ => None
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParserError {
+  Expected(ExpectedError),
+  UndefinedVariable { name: String, span: Span },
+  UndefinedMethod { name: String, span: Span },
+  DuplicateVariable { name: String, span: Span },
+  DuplicateMethod { name: String, span: Span },
+  ArityMismatch { name: String, expected: usize, found: usize, span: Span },
+  ReturnOutsideMethod { span: Span },
+  NotAnArray { name: String, span: Span },
+  IndexOutOfBounds { name: String, index: MemoryUnit, len: MemoryUnit, span: Span },
+  EmptyArray { name: String, span: Span },
+  UndefinedStruct { name: String, span: Span },
+  DuplicateStruct { name: String, span: Span },
+  DuplicateField { strukt: String, field: String, span: Span },
+  UndefinedField { strukt: String, field: String, span: Span },
+  NotAStruct { name: String, span: Span },
+  NotAStructPointer { name: String, span: Span },
+  InvalidAssignment { span: Span },
+}
+
+impl ParserError {
+  pub fn span(&self) -> &Span {
+    match self {
+      Self::Expected(e) => &e.span,
+      Self::UndefinedVariable { span, .. } |
+      Self::UndefinedMethod { span, .. } |
+      Self::DuplicateVariable { span, .. } |
+      Self::DuplicateMethod { span, .. } |
+      Self::ArityMismatch { span, .. } |
+      Self::ReturnOutsideMethod { span } |
+      Self::NotAnArray { span, .. } |
+      Self::IndexOutOfBounds { span, .. } |
+      Self::EmptyArray { span, .. } |
+      Self::UndefinedStruct { span, .. } |
+      Self::DuplicateStruct { span, .. } |
+      Self::DuplicateField { span, .. } |
+      Self::UndefinedField { span, .. } |
+      Self::NotAStruct { span, .. } |
+      Self::NotAStructPointer { span, .. } |
+      Self::InvalidAssignment { span } => span
+    }
+  }
+}
+
+impl Display for ParserError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Expected(e) => write!(f, "{}", e),
+      Self::UndefinedVariable { name, .. } => write!(f, "Variable '{}' does not exist", name),
+      Self::UndefinedMethod { name, .. } => write!(f, "Method '{}' does not exist", name),
+      Self::DuplicateVariable { name, .. } => write!(f, "Variable '{}' already exists", name),
+      Self::DuplicateMethod { name, .. } => write!(f, "Method '{}' already exists", name),
+      Self::ArityMismatch { name, expected, found, .. } => write!(f, "Method '{}' takes {} parameters but {} were supplied", name, expected, found),
+      Self::ReturnOutsideMethod { .. } => write!(f, "Cannot return outside of a method"),
+      Self::NotAnArray { name, .. } => write!(f, "Variable '{}' is not an array", name),
+      Self::IndexOutOfBounds { name, index, len, .. } => write!(f, "Index {} is out of bounds for array '{}' of length {}", index, name, len),
+      Self::EmptyArray { name, .. } => write!(f, "Array '{}' must have at least one element", name),
+      Self::UndefinedStruct { name, .. } => write!(f, "Struct '{}' does not exist", name),
+      Self::DuplicateStruct { name, .. } => write!(f, "Struct '{}' already exists", name),
+      Self::DuplicateField { strukt, field, .. } => write!(f, "Field '{}' is declared twice in struct '{}'", field, strukt),
+      Self::UndefinedField { strukt, field, .. } => write!(f, "Struct '{}' has no field '{}'", strukt, field),
+      Self::NotAStruct { name, .. } => write!(f, "Variable '{}' is not a struct", name),
+      Self::NotAStructPointer { name, .. } => write!(f, "Variable '{}' is not a struct pointer", name),
+      Self::InvalidAssignment { .. } => write!(f, "Cannot assign to this expression"),
+    }
+  }
+}
+
+impl Error for ParserError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      Self::Expected(e) => Some(e),
+      _ => None
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompilerError {
+  OutOfMemory { span: Span },
+  UnstableReference { expr: String, span: Span },
+  UndefinedMethod { id: u64, span: Span },
+  ReturnOutsideMethod { span: Span },
+  InvalidNode { span: Span },
+  UndefinedVariable { name: String, span: Span },
+  FieldOutOfRange { name: String, offset: MemoryUnit, size: usize, span: Span },
+}
+
+impl CompilerError {
+  pub fn span(&self) -> &Span {
+    match self {
+      Self::OutOfMemory { span } |
+      Self::UnstableReference { span, .. } |
+      Self::UndefinedMethod { span, .. } |
+      Self::ReturnOutsideMethod { span } |
+      Self::InvalidNode { span } |
+      Self::UndefinedVariable { span, .. } |
+      Self::FieldOutOfRange { span, .. } => span
+    }
+  }
+}
+
+impl Display for CompilerError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::OutOfMemory { .. } => write!(f, "Not enough memory!"),
+      Self::UnstableReference { expr, .. } => write!(f, "Cannot reference an unstable address {}", expr),
+      Self::UndefinedMethod { id, .. } => write!(f, "Method #{} was called before being generated", id),
+      Self::ReturnOutsideMethod { .. } => write!(f, "Cannot return outside of a method"),
+      Self::InvalidNode { .. } => write!(f, "Cannot generate code for an invalid node"),
+      Self::UndefinedVariable { name, .. } => write!(f, "Variable '{}' has no memory allocated", name),
+      Self::FieldOutOfRange { name, offset, size, .. } => write!(f, "Field offset {} is outside of '{}', which has {} cells", offset, name, size),
+    }
+  }
+}
+
+impl Error for CompilerError { }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConverterError {
+  Expected(ExpectedError),
+  UndefinedLabel { name: String, span: Span },
+  DuplicateLabel { name: String, span: Span },
+  InvalidInstruction { ip: usize, span: Span },
+}
+
+impl ConverterError {
+  pub fn span(&self) -> &Span {
+    match self {
+      Self::Expected(e) => &e.span,
+      Self::UndefinedLabel { span, .. } | Self::DuplicateLabel { span, .. } | Self::InvalidInstruction { span, .. } => span
+    }
+  }
+}
+
+impl Display for ConverterError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Expected(e) => write!(f, "{}", e),
+      Self::UndefinedLabel { name, .. } => write!(f, "Label '{}' does not exist", name),
+      Self::DuplicateLabel { name, .. } => write!(f, "Label '{}' already exists", name),
+      Self::InvalidInstruction { ip, .. } => write!(f, "Cannot write an invalid instruction at {} as assembly", ip),
+    }
+  }
+}
+
+impl Error for ConverterError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      Self::Expected(e) => Some(e),
+      _ => None
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterpreterError {
+  InvalidAddress { addr: MemoryUnit, memory: usize, ip: usize, span: Span },
+  UndefinedLabel { name: String, ip: usize, span: Span },
+  UnexpectedInstruction { instruction: Instruction, ip: usize, span: Span },
+  ReturnStackOverflow { ip: usize, span: Span },
+  ReturnStackUnderflow { ip: usize, span: Span },
+  Io { kind: ErrorKind, ip: usize, span: Span },
+  EndOfInput { ip: usize, span: Span },
+  ArithmeticOverflow { instruction: Instruction, left: MemoryUnit, right: MemoryUnit, ip: usize, span: Span },
+  DivisionByZero { dividend: MemoryUnit, ip: usize, span: Span },
+  BudgetExhausted { budget: Budget, ip: usize, memory: Vec<MemoryUnit>, span: Span },
+  IndexOutOfBounds { name: String, index: MemoryUnit, len: MemoryUnit, ip: usize, span: Span },
+  CallStackOverflow { sp: MemoryUnit, limit: MemoryUnit, ip: usize, span: Span },
+}
+
+impl InterpreterError {
+  pub fn span(&self) -> &Span {
+    match self {
+      Self::InvalidAddress { span, .. } |
+      Self::UndefinedLabel { span, .. } |
+      Self::UnexpectedInstruction { span, .. } |
+      Self::ReturnStackOverflow { span, .. } |
+      Self::ReturnStackUnderflow { span, .. } |
+      Self::Io { span, .. } |
+      Self::EndOfInput { span, .. } |
+      Self::ArithmeticOverflow { span, .. } |
+      Self::DivisionByZero { span, .. } |
+      Self::BudgetExhausted { span, .. } |
+      Self::IndexOutOfBounds { span, .. } |
+      Self::CallStackOverflow { span, .. } => span
+    }
+  }
+}
+
+impl Display for InterpreterError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::InvalidAddress { addr, ip, .. } => write!(f, "Invalid address {} at instruction {}", addr, ip),
+      Self::UndefinedLabel { name, ip, .. } => write!(f, "Jump to unresolved label '{}' at instruction {}", name, ip),
+      Self::UnexpectedInstruction { instruction, ip, .. } => write!(f, "Unexpected Instruction '{}' at instruction {}", instruction, ip),
+      Self::ReturnStackOverflow { ip, .. } => write!(f, "Return stack overflow at instruction {}", ip),
+      Self::ReturnStackUnderflow { ip, .. } => write!(f, "Return with an empty return stack at instruction {}", ip),
+      Self::Io { kind, ip, .. } => write!(f, "I/O error ({}) at instruction {}", kind, ip),
+      Self::EndOfInput { ip, .. } => write!(f, "Read past the end of input at instruction {}", ip),
+      Self::ArithmeticOverflow { instruction, left, right, ip, .. } => write!(f, "Arithmetic overflow in '{}' with operands {} and {} at instruction {}", instruction, left, right, ip),
+      Self::DivisionByZero { dividend, ip, .. } => write!(f, "Division of {} by zero at instruction {}", dividend, ip),
+      Self::BudgetExhausted { budget: Budget::Instructions(count), ip, .. } => write!(f, "Instruction budget of {} exhausted at instruction {}", count, ip),
+      Self::BudgetExhausted { budget: Budget::Time(timeout), ip, .. } => write!(f, "Timeout of {:?} exceeded at instruction {}", timeout, ip),
+      Self::IndexOutOfBounds { name, index, len, ip, .. } => write!(f, "Index {} out of bounds for '{}' of length {} at instruction {}", index, name, len, ip),
+      Self::CallStackOverflow { sp, ip, .. } => write!(f, "Call stack overflow with the stack pointer at {} at instruction {}", sp, ip),
+    }
+  }
+}
+
+impl Error for InterpreterError { }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BinaryError {
+  BadMagic,
+  UnsupportedVersion { found: u16, expected: u16 },
+  UnsupportedWordSize { found: u32 },
+  UnsupportedMemorySize { found: usize, bits: u32 },
+  IncompatibleWordSize { found: u32, expected: u32 },
+  IncompatibleMemorySize { found: usize, expected: usize },
+  Truncated { offset: usize },
+  InvalidOpcode { opcode: u8, offset: usize },
+  InvalidLabel { index: u32, offset: usize },
+  InvalidFile { index: u32, offset: usize },
+  InvalidString { offset: usize },
+}
+
+impl Display for BinaryError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::BadMagic => write!(f, "Not a bytecode file"),
+      Self::UnsupportedVersion { found, expected } => write!(f, "Unsupported bytecode version {}, expected {}", found, expected),
+      Self::UnsupportedWordSize { found } => write!(f, "Bytecode was built for unsupported {}-bit cells", found),
+      Self::UnsupportedMemorySize { found, bits } => write!(f, "Bytecode was built for {} cells of memory, which {}-bit cells cannot support", found, bits),
+      Self::IncompatibleWordSize { found, expected } => write!(f, "Bytecode was built for {}-bit cells but {}-bit cells were requested", found, expected),
+      Self::IncompatibleMemorySize { found, expected } => write!(f, "Bytecode was built for {} cells of memory but {} were requested", found, expected),
+      Self::Truncated { offset } => write!(f, "Bytecode file ends unexpectedly at byte {}", offset),
+      Self::InvalidOpcode { opcode, offset } => write!(f, "Invalid opcode {:#04X} at byte {}", opcode, offset),
+      Self::InvalidLabel { index, offset } => write!(f, "Invalid label index {} at byte {}", index, offset),
+      Self::InvalidFile { index, offset } => write!(f, "Invalid file index {} at byte {}", index, offset),
+      Self::InvalidString { offset } => write!(f, "Invalid UTF-8 string at byte {}", offset),
+    }
+  }
+}
+
+impl Error for BinaryError { }
 
 #[derive(Debug)]
 pub enum DSAsmError {
-  ProcessorError(String),
-  TokenizerError(String),
-  InterpreterError(String),
-  ArgumentError(String),
-  GenericError(String),
-  FileError(String),
-  ParserError(String),
-  CompilerError(String),
-  ConverterError(String),
+  Argument(String),
+  File { path: String, source: std::io::Error },
+  Tokenizer(TokenizerError),
+  Parser(ParserError),
+  Compiler(CompilerError),
+  Converter(ConverterError),
+  Interpreter(InterpreterError),
+  Binary(BinaryError),
+  Multiple { errors: Vec<DSAsmError>, truncated: bool },
+}
+
+impl DSAsmError {
+  pub fn errors(&self) -> Vec<&DSAsmError> {
+    match self {
+      DSAsmError::Multiple { errors, .. } => errors.iter().flat_map(|e| e.errors()).collect(),
+      e => vec![e]
+    }
+  }
+
+  pub fn span(&self) -> Option<&Span> {
+    match self {
+      DSAsmError::Tokenizer(e) => Some(e.span()),
+      DSAsmError::Parser(e) => Some(e.span()),
+      DSAsmError::Compiler(e) => Some(e.span()),
+      DSAsmError::Converter(e) => Some(e.span()),
+      DSAsmError::Interpreter(e) => Some(e.span()),
+      DSAsmError::Argument(_) | DSAsmError::File { .. } | DSAsmError::Binary(_) | DSAsmError::Multiple { .. } => None
+    }
+  }
+
+  pub fn code(&self) -> &'static str {
+    match self {
+      DSAsmError::Tokenizer(TokenizerError::InvalidCharacter { .. }) => "E0101",
+      DSAsmError::Tokenizer(TokenizerError::InvalidLiteral { .. }) => "E0102",
+      DSAsmError::Tokenizer(TokenizerError::InvalidEscape { .. }) => "E0103",
+      DSAsmError::Tokenizer(TokenizerError::UnterminatedString { .. }) => "E0104",
+      DSAsmError::Tokenizer(TokenizerError::CharOutOfRange { .. }) => "E0105",
+      DSAsmError::Tokenizer(TokenizerError::LiteralOverflow { .. }) => "E0106",
+      DSAsmError::Parser(ParserError::Expected(_)) => "E0201",
+      DSAsmError::Parser(ParserError::UndefinedVariable { .. }) => "E0202",
+      DSAsmError::Parser(ParserError::UndefinedMethod { .. }) => "E0203",
+      DSAsmError::Parser(ParserError::DuplicateVariable { .. }) => "E0204",
+      DSAsmError::Parser(ParserError::DuplicateMethod { .. }) => "E0205",
+      DSAsmError::Parser(ParserError::ArityMismatch { .. }) => "E0206",
+      DSAsmError::Parser(ParserError::ReturnOutsideMethod { .. }) => "E0207",
+      DSAsmError::Parser(ParserError::NotAnArray { .. }) => "E0208",
+      DSAsmError::Parser(ParserError::IndexOutOfBounds { .. }) => "E0209",
+      DSAsmError::Parser(ParserError::EmptyArray { .. }) => "E0210",
+      DSAsmError::Parser(ParserError::UndefinedStruct { .. }) => "E0211",
+      DSAsmError::Parser(ParserError::DuplicateStruct { .. }) => "E0212",
+      DSAsmError::Parser(ParserError::DuplicateField { .. }) => "E0213",
+      DSAsmError::Parser(ParserError::UndefinedField { .. }) => "E0214",
+      DSAsmError::Parser(ParserError::NotAStruct { .. }) => "E0215",
+      DSAsmError::Parser(ParserError::NotAStructPointer { .. }) => "E0216",
+      DSAsmError::Parser(ParserError::InvalidAssignment { .. }) => "E0217",
+      DSAsmError::Compiler(CompilerError::OutOfMemory { .. }) => "E0301",
+      DSAsmError::Compiler(CompilerError::UnstableReference { .. }) => "E0302",
+      DSAsmError::Compiler(CompilerError::UndefinedMethod { .. }) => "E0303",
+      DSAsmError::Compiler(CompilerError::ReturnOutsideMethod { .. }) => "E0304",
+      DSAsmError::Compiler(CompilerError::InvalidNode { .. }) => "E0305",
+      DSAsmError::Compiler(CompilerError::UndefinedVariable { .. }) => "E0306",
+      DSAsmError::Compiler(CompilerError::FieldOutOfRange { .. }) => "E0307",
+      DSAsmError::Converter(ConverterError::Expected(_)) => "E0401",
+      DSAsmError::Converter(ConverterError::UndefinedLabel { .. }) => "E0402",
+      DSAsmError::Converter(ConverterError::DuplicateLabel { .. }) => "E0403",
+      DSAsmError::Converter(ConverterError::InvalidInstruction { .. }) => "E0404",
+      DSAsmError::Interpreter(InterpreterError::InvalidAddress { .. }) => "E0501",
+      DSAsmError::Interpreter(InterpreterError::UndefinedLabel { .. }) => "E0502",
+      DSAsmError::Interpreter(InterpreterError::UnexpectedInstruction { .. }) => "E0503",
+      DSAsmError::Interpreter(InterpreterError::ReturnStackOverflow { .. }) => "E0504",
+      DSAsmError::Interpreter(InterpreterError::ReturnStackUnderflow { .. }) => "E0505",
+      DSAsmError::Interpreter(InterpreterError::Io { .. }) => "E0506",
+      DSAsmError::Interpreter(InterpreterError::EndOfInput { .. }) => "E0507",
+      DSAsmError::Interpreter(InterpreterError::ArithmeticOverflow { .. }) => "E0508",
+      DSAsmError::Interpreter(InterpreterError::DivisionByZero { .. }) => "E0509",
+      DSAsmError::Interpreter(InterpreterError::BudgetExhausted { .. }) => "E0510",
+      DSAsmError::Interpreter(InterpreterError::IndexOutOfBounds { .. }) => "E0511",
+      DSAsmError::Interpreter(InterpreterError::CallStackOverflow { .. }) => "E0512",
+      DSAsmError::Binary(BinaryError::BadMagic) => "E0601",
+      DSAsmError::Binary(BinaryError::UnsupportedVersion { .. }) => "E0602",
+      DSAsmError::Binary(BinaryError::UnsupportedWordSize { .. }) => "E0603",
+      DSAsmError::Binary(BinaryError::UnsupportedMemorySize { .. }) => "E0604",
+      DSAsmError::Binary(BinaryError::Truncated { .. }) => "E0605",
+      DSAsmError::Binary(BinaryError::InvalidOpcode { .. }) => "E0606",
+      DSAsmError::Binary(BinaryError::InvalidLabel { .. }) => "E0607",
+      DSAsmError::Binary(BinaryError::InvalidFile { .. }) => "E0608",
+      DSAsmError::Binary(BinaryError::InvalidString { .. }) => "E0609",
+      DSAsmError::Binary(BinaryError::IncompatibleWordSize { .. }) => "E0610",
+      DSAsmError::Binary(BinaryError::IncompatibleMemorySize { .. }) => "E0611",
+      DSAsmError::Argument(_) => "E0901",
+      DSAsmError::File { .. } => "E0902",
+      DSAsmError::Multiple { .. } => "E0000",
+    }
+  }
+
+  pub fn help(&self) -> Option<String> {
+    match self {
+      DSAsmError::Tokenizer(TokenizerError::InvalidLiteral { .. }) => Some("literals are decimal, or prefixed with 0x, 0b or 0o, and may use `_` as a separator".to_string()),
+      DSAsmError::Tokenizer(TokenizerError::LiteralOverflow { bits, .. }) => Some(format!("the largest {}-bit value is {}", bits, (1u64 << bits) - 1)),
+      DSAsmError::Tokenizer(TokenizerError::InvalidEscape { .. }) => Some("supported escapes are \\n, \\t, \\r, \\0, \\\\, \\', \\\" and \\xNN".to_string()),
+      DSAsmError::Parser(ParserError::UndefinedVariable { name, .. }) => Some(format!("declare it first with `let {} = ...`", name)),
+      DSAsmError::Parser(ParserError::UndefinedMethod { name, .. }) => Some(format!("methods must be declared before use with `method {}(...)`", name)),
+      DSAsmError::Parser(ParserError::DuplicateVariable { name, .. }) => Some(format!("assign the existing variable with `{} = ...` instead", name)),
+      DSAsmError::Parser(ParserError::NotAnArray { name, .. }) => Some(format!("declare it as an array with `let {}[N];`", name)),
+      DSAsmError::Parser(ParserError::IndexOutOfBounds { len, .. }) => Some(format!("valid indices are 0 to {}", len - 1)),
+      DSAsmError::Parser(ParserError::UndefinedStruct { name, .. }) => Some(format!("declare it first with `struct {} {{ ... }}`", name)),
+      DSAsmError::Parser(ParserError::NotAStruct { name, .. }) => Some(format!("use `{}->field` to access a field through a pointer", name)),
+      DSAsmError::Parser(ParserError::NotAStructPointer { name, .. }) => Some(format!("declare it as a pointer with `let {}: *Name = ...`", name)),
+      DSAsmError::Parser(ParserError::InvalidAssignment { .. }) => Some("only variables, array elements, struct fields and dereferences can be assigned".to_string()),
+      DSAsmError::Interpreter(InterpreterError::UndefinedLabel { .. }) => Some("link the program with bytecode::link before running it".to_string()),
+      DSAsmError::Interpreter(InterpreterError::InvalidAddress { memory, .. }) => Some(format!("addresses must be lower than {}", memory)),
+      DSAsmError::Interpreter(InterpreterError::ReturnStackOverflow { .. }) => Some(format!("calls cannot be nested deeper than {}", crate::core::interpreter::Interpreter::RETURN_STACK_SIZE)),
+      DSAsmError::Interpreter(InterpreterError::EndOfInput { .. }) => Some("choose what `,` stores at end of input with --eof unchanged|zero|max".to_string()),
+      DSAsmError::Interpreter(InterpreterError::ArithmeticOverflow { .. }) => Some("run without -trap to let arithmetic wrap around".to_string()),
+      DSAsmError::Interpreter(InterpreterError::BudgetExhausted { .. }) => Some("raise the limit with --max-instructions or --timeout if the program is expected to run longer".to_string()),
+      DSAsmError::Interpreter(InterpreterError::ReturnStackUnderflow { .. }) => Some("every `;` must be reached through a matching `&label` call".to_string()),
+      DSAsmError::Interpreter(InterpreterError::IndexOutOfBounds { len: 0, .. }) => Some("the array is empty".to_string()),
+      DSAsmError::Interpreter(InterpreterError::IndexOutOfBounds { len, .. }) => Some(format!("valid indices are 0 to {}", len - 1)),
+      DSAsmError::Interpreter(InterpreterError::CallStackOverflow { .. }) => Some("method frames live in the top quarter of memory, raise --memory or nest fewer calls".to_string()),
+      DSAsmError::Binary(BinaryError::UnsupportedWordSize { .. }) => Some("cells must be 8, 16 or 32 bits wide".to_string()),
+      DSAsmError::Binary(BinaryError::UnsupportedMemorySize { bits, .. }) => Some(format!("{}-bit memory holds between 1 and {} cells", bits, Machine::new(*bits, 0).max_memory())),
+      DSAsmError::Binary(BinaryError::IncompatibleWordSize { .. } | BinaryError::IncompatibleMemorySize { .. }) => Some("drop --bits and --memory to run the image as built, or recompile the source with them".to_string()),
+      _ => None
+    }
+  }
 }
 
 impl Display for DSAsmError {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
-      DSAsmError::ProcessorError(e) => write!(f, "ProcessorError: {}", e),
-      DSAsmError::TokenizerError(e) => write!(f, "TokenizerError: {}", e),
-      DSAsmError::InterpreterError(e) => write!(f, "InterpreterError: {}", e),
-      DSAsmError::ArgumentError(e) => write!(f, "ArgumentError: {}", e),
-      DSAsmError::GenericError(e) => write!(f, "GenericError: {}", e),
-      DSAsmError::FileError(e) => write!(f, "FileError: {}", e),
-      DSAsmError::ParserError(e) => write!(f, "ParserError: {}", e),
-      DSAsmError::CompilerError(e) => write!(f, "CompilerError: {}", e),
-      DSAsmError::ConverterError(e) => write!(f, "ConverterError: {}", e),
+      DSAsmError::Argument(e) => write!(f, "{}", e),
+      DSAsmError::File { path, source } => write!(f, "Cannot access '{}': {}", path, source),
+      DSAsmError::Tokenizer(e) => write!(f, "{}", e),
+      DSAsmError::Parser(e) => write!(f, "{}", e),
+      DSAsmError::Compiler(e) => write!(f, "{}", e),
+      DSAsmError::Converter(e) => write!(f, "{}", e),
+      DSAsmError::Interpreter(e) => write!(f, "{}", e),
+      DSAsmError::Binary(e) => write!(f, "{}", e),
+      DSAsmError::Multiple { errors, truncated } => write!(f, "aborting due to {}{} errors", if *truncated { "more than " } else { "" }, errors.len()),
+    }
+  }
+}
+
+impl Error for DSAsmError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      DSAsmError::Argument(_) => None,
+      DSAsmError::Multiple { errors, .. } => errors.first().map(|e| e as &(dyn Error + 'static)),
+      DSAsmError::File { source, .. } => Some(source),
+      DSAsmError::Tokenizer(e) => Some(e),
+      DSAsmError::Parser(e) => Some(e),
+      DSAsmError::Compiler(e) => Some(e),
+      DSAsmError::Converter(e) => Some(e),
+      DSAsmError::Interpreter(e) => Some(e),
+      DSAsmError::Binary(e) => Some(e),
     }
   }
 }
 
-impl std::error::Error for DSAsmError { }
+impl From<TokenizerError> for DSAsmError {
+  fn from(value: TokenizerError) -> Self {
+    DSAsmError::Tokenizer(value)
+  }
+}
+
+impl From<ParserError> for DSAsmError {
+  fn from(value: ParserError) -> Self {
+    DSAsmError::Parser(value)
+  }
+}
+
+impl From<CompilerError> for DSAsmError {
+  fn from(value: CompilerError) -> Self {
+    DSAsmError::Compiler(value)
+  }
+}
+
+impl From<ConverterError> for DSAsmError {
+  fn from(value: ConverterError) -> Self {
+    DSAsmError::Converter(value)
+  }
+}
+
+impl From<BinaryError> for DSAsmError {
+  fn from(value: BinaryError) -> Self {
+    DSAsmError::Binary(value)
+  }
+}
+
+impl From<InterpreterError> for DSAsmError {
+  fn from(value: InterpreterError) -> Self {
+    DSAsmError::Interpreter(value)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::HashSet, time::Duration};
+
+  use super::*;
 
-impl<V, E: Display> From<Result<V, E>>  for DSAsmError {
-  fn from(value: Result<V, E>) -> Self {
-    DSAsmError::GenericError(format!("{}", value.err().expect("Result is not error")))
+  fn every_error() -> Vec<DSAsmError> {
+    let span = Span::default();
+    let expected = ExpectedError { expected: "a".to_string(), found: "b".to_string(), span: span.clone() };
+    let name = || "x".to_string();
+    let tokenizer = vec![
+      TokenizerError::InvalidCharacter { ch: '?', span: span.clone() },
+      TokenizerError::InvalidLiteral { literal: name(), span: span.clone(), source: "x".parse::<u32>().unwrap_err() },
+      TokenizerError::LiteralOverflow { literal: name(), bits: 8, span: span.clone() },
+      TokenizerError::InvalidEscape { sequence: name(), span: span.clone() },
+      TokenizerError::UnterminatedString { span: span.clone() },
+      TokenizerError::CharOutOfRange { ch: '?', span: span.clone() },
+    ];
+    let parser = vec![
+      ParserError::Expected(expected.clone()),
+      ParserError::UndefinedVariable { name: name(), span: span.clone() },
+      ParserError::UndefinedMethod { name: name(), span: span.clone() },
+      ParserError::DuplicateVariable { name: name(), span: span.clone() },
+      ParserError::DuplicateMethod { name: name(), span: span.clone() },
+      ParserError::ArityMismatch { name: name(), expected: 1, found: 2, span: span.clone() },
+      ParserError::ReturnOutsideMethod { span: span.clone() },
+      ParserError::NotAnArray { name: name(), span: span.clone() },
+      ParserError::IndexOutOfBounds { name: name(), index: 2, len: 1, span: span.clone() },
+      ParserError::EmptyArray { name: name(), span: span.clone() },
+      ParserError::UndefinedStruct { name: name(), span: span.clone() },
+      ParserError::DuplicateStruct { name: name(), span: span.clone() },
+      ParserError::DuplicateField { strukt: name(), field: name(), span: span.clone() },
+      ParserError::UndefinedField { strukt: name(), field: name(), span: span.clone() },
+      ParserError::NotAStruct { name: name(), span: span.clone() },
+      ParserError::NotAStructPointer { name: name(), span: span.clone() },
+      ParserError::InvalidAssignment { span: span.clone() },
+    ];
+    let compiler = vec![
+      CompilerError::OutOfMemory { span: span.clone() },
+      CompilerError::UnstableReference { expr: name(), span: span.clone() },
+      CompilerError::UndefinedMethod { id: 0, span: span.clone() },
+      CompilerError::ReturnOutsideMethod { span: span.clone() },
+      CompilerError::InvalidNode { span: span.clone() },
+      CompilerError::UndefinedVariable { name: name(), span: span.clone() },
+      CompilerError::FieldOutOfRange { name: name(), offset: 1, size: 1, span: span.clone() },
+    ];
+    let converter = vec![
+      ConverterError::Expected(expected),
+      ConverterError::UndefinedLabel { name: name(), span: span.clone() },
+      ConverterError::DuplicateLabel { name: name(), span: span.clone() },
+      ConverterError::InvalidInstruction { ip: 0, span: span.clone() },
+    ];
+    let interpreter = vec![
+      InterpreterError::InvalidAddress { addr: 1, memory: 1, ip: 0, span: span.clone() },
+      InterpreterError::UndefinedLabel { name: name(), ip: 0, span: span.clone() },
+      InterpreterError::UnexpectedInstruction { instruction: Instruction::Print, ip: 0, span: span.clone() },
+      InterpreterError::ReturnStackOverflow { ip: 0, span: span.clone() },
+      InterpreterError::ReturnStackUnderflow { ip: 0, span: span.clone() },
+      InterpreterError::Io { kind: ErrorKind::Other, ip: 0, span: span.clone() },
+      InterpreterError::EndOfInput { ip: 0, span: span.clone() },
+      InterpreterError::ArithmeticOverflow { instruction: Instruction::Print, left: 1, right: 1, ip: 0, span: span.clone() },
+      InterpreterError::DivisionByZero { dividend: 1, ip: 0, span: span.clone() },
+      InterpreterError::BudgetExhausted { budget: Budget::Time(Duration::ZERO), ip: 0, memory: vec![], span: span.clone() },
+      InterpreterError::IndexOutOfBounds { name: name(), index: 2, len: 1, ip: 0, span: span.clone() },
+      InterpreterError::CallStackOverflow { sp: 2, limit: 1, ip: 0, span },
+    ];
+    let binary = vec![
+      BinaryError::BadMagic,
+      BinaryError::UnsupportedVersion { found: 2, expected: 1 },
+      BinaryError::UnsupportedWordSize { found: 7 },
+      BinaryError::UnsupportedMemorySize { found: 0, bits: 8 },
+      BinaryError::Truncated { offset: 0 },
+      BinaryError::InvalidOpcode { opcode: 0xff, offset: 0 },
+      BinaryError::InvalidLabel { index: 0, offset: 0 },
+      BinaryError::InvalidFile { index: 0, offset: 0 },
+      BinaryError::InvalidString { offset: 0 },
+      BinaryError::IncompatibleWordSize { found: 8, expected: 16 },
+      BinaryError::IncompatibleMemorySize { found: 8, expected: 16 },
+    ];
+
+    let mut errors: Vec<DSAsmError> = vec![
+      DSAsmError::Argument(name()),
+      DSAsmError::File { path: name(), source: std::io::Error::from(ErrorKind::NotFound) },
+      DSAsmError::Multiple { errors: vec![], truncated: false },
+    ];
+    errors.extend(tokenizer.into_iter().map(DSAsmError::from));
+    errors.extend(parser.into_iter().map(DSAsmError::from));
+    errors.extend(compiler.into_iter().map(DSAsmError::from));
+    errors.extend(converter.into_iter().map(DSAsmError::from));
+    errors.extend(interpreter.into_iter().map(DSAsmError::from));
+    errors.extend(binary.into_iter().map(DSAsmError::from));
+    errors
+  }
+
+  #[test]
+  fn error_codes_are_unique() {
+    let errors = every_error();
+    let codes: HashSet<_> = errors.iter().map(|e| e.code()).collect();
+    assert_eq!(codes.len(), errors.len());
+  }
+
+  #[test]
+  fn error_codes_are_sequential_within_each_stage() {
+    let mut codes: Vec<u32> = every_error().iter()
+      .map(|e| e.code()[1..].parse().unwrap())
+      .filter(|&code| code != 0)
+      .collect();
+    codes.sort();
+    for pair in codes.windows(2) {
+      let (prev, next) = (pair[0], pair[1]);
+      assert!(next == prev + 1 || next / 100 != prev / 100, "gap between E{:04} and E{:04}", prev, next);
+    }
   }
 }
@@ -1,4 +1,4 @@
-use crate::core::{generation::Generator, interpreter::{Interpreter, MemoryUnit}, tokenizer::Token};
+use crate::core::{generation::Generator, interpreter::MemoryUnit, tokenizer::Token};
 
 impl Generator {
   pub fn goto(&mut self, addr: MemoryUnit) {
@@ -7,13 +7,19 @@ impl Generator {
     self.push(Token::Literal(addr));
   }
   pub fn add(&mut self, val: MemoryUnit) {
-    for _ in 0..val {
-      self.push(Token::Plus);
-    }
+    self.step(Token::Plus, val);
   }
   pub fn sub(&mut self, val: MemoryUnit) {
-    for _ in 0..val {
-      self.push(Token::Minus);
+    self.step(Token::Minus, val);
+  }
+  fn step(&mut self, token: Token, val: MemoryUnit) {
+    match val {
+      0 => { },
+      1 => self.push(token),
+      val => {
+        self.push(token);
+        self.push(Token::Literal(val));
+      }
     }
   }
   pub fn goto_ins(&mut self, val: MemoryUnit) {
@@ -51,6 +57,28 @@ impl Generator {
     self.push(Token::Slash);
     self.push(Token::Literal(r));
   }
+  pub fn deref(&mut self, addr: MemoryUnit) {
+    self.push(Token::OpenSquare);
+    self.push(Token::Literal(addr));
+    self.push(Token::CloseSquare);
+  }
+  pub fn store(&mut self, addr: MemoryUnit) {
+    self.push(Token::Equals);
+    self.push(Token::OpenSquare);
+    self.push(Token::Literal(addr));
+    self.push(Token::CloseSquare);
+  }
+  pub fn bounds(&mut self, name: &str, len: MemoryUnit) {
+    self.push(Token::Percent);
+    self.push(Token::Identifier(name.to_string()));
+    self.push(Token::OpenSquare);
+    self.push(Token::Literal(len));
+    self.push(Token::CloseSquare);
+  }
+  pub fn stack_limit(&mut self, limit: MemoryUnit) {
+    self.push(Token::Pipe);
+    self.push(Token::Literal(limit));
+  }
   pub fn invert(&mut self) {
     self.push(Token::Exclamation);
   }
@@ -1,6 +1,6 @@
 use std::sync::atomic::{AtomicU64, Ordering};
 
-use crate::core::{error::DSAsmError, generation::{Cell, Generator}, interpreter::{Interpreter, MemoryUnit}, tokenizer::Token};
+use crate::core::{error::{CompilerError, DSAsmError}, generation::{Cell, Generator}, interpreter::MemoryUnit, tokenizer::Token};
 
 static CURR_LABEL: AtomicU64 = AtomicU64::new(0);
 fn generate_id() -> u64 {
@@ -8,24 +8,69 @@ fn generate_id() -> u64 {
 }
 
 impl Generator {
-  pub fn alloc(&mut self) -> Result<MemoryUnit, DSAsmError> {
-    if let Some((i, cell)) = self.stack.iter_mut().enumerate().find(|(_, cell)| cell.is_unused()) {
-      *cell = Cell::Used;
+  pub fn new_label(&self, name: &str) -> String {
+    format!("__{}_{}", generate_id(), name)
+  }
+
+  // Frame cells are saved around every call, so only other methods may reuse them.
+  fn is_free(&self, cell: &Cell) -> bool {
+    cell.is_unused() || (self.frame.is_some() && cell.is_frame())
+  }
+
+  fn alloc_as(&mut self, kind: Cell) -> Result<MemoryUnit, DSAsmError> {
+    if let Some(i) = self.stack.iter().position(|cell| self.is_free(cell)) {
+      self.stack[i] = kind;
+      if let Some(frame) = self.frame.as_mut() {
+        frame.touched.insert(i as MemoryUnit);
+      }
       Ok(i as MemoryUnit)
     } else {
-      Err(DSAsmError::CompilerError("Not enough memory!".into()))
+      Err(CompilerError::OutOfMemory { span: self.span.clone() }.into())
     }
   }
 
+  pub fn alloc(&mut self) -> Result<MemoryUnit, DSAsmError> {
+    self.alloc_as(Cell::Used)
+  }
+
   pub fn alloc_temp(&mut self) -> Result<MemoryUnit, DSAsmError> {
-    if let Some((i, cell)) = self.stack.iter_mut().enumerate().find(|(_, cell)| cell.is_unused()) {
-      *cell = Cell::Temporary;
-      Ok(i as MemoryUnit)
-    } else {
-      Err(DSAsmError::CompilerError("Not enough memory!".into()))
+    self.alloc_as(Cell::Temporary)
+  }
+
+  pub fn alloc_array(&mut self, id: u64, len: MemoryUnit) -> Result<MemoryUnit, DSAsmError> {
+    self.alloc_block(Cell::Variable(id), len as usize)
+  }
+
+  pub fn alloc_block(&mut self, kind: Cell, len: usize) -> Result<MemoryUnit, DSAsmError> {
+    let len = len.max(1);
+    let Some(base) = self.stack.windows(len).position(|cells| cells.iter().all(|cell| self.is_free(cell))) else {
+      return Err(CompilerError::OutOfMemory { span: self.span.clone() }.into());
+    };
+    for addr in base..base + len {
+      self.stack[addr] = kind;
+      if let Cell::Variable(id) = kind {
+        self.symbols.entry(addr as MemoryUnit).or_default().insert(id);
+      }
+      if let Some(frame) = self.frame.as_mut() {
+        frame.touched.insert(addr as MemoryUnit);
+      }
+      self.clear(addr as MemoryUnit);
+    }
+    Ok(base as MemoryUnit)
+  }
+
+  pub fn variable(&self, id: u64) -> Result<MemoryUnit, DSAsmError> {
+    match self.stack.iter().position(|cell| cell.is_variable_of_id(id)) {
+      Some(addr) => Ok(addr as MemoryUnit),
+      None => Err(CompilerError::UndefinedVariable { name: self.names.get(&id).cloned().unwrap_or_else(|| format!("#{}", id)), span: self.span.clone() }.into())
     }
   }
 
+  pub fn bind(&mut self, addr: MemoryUnit, id: u64) {
+    self.stack[addr as usize] = Cell::Variable(id);
+    self.symbols.entry(addr).or_default().insert(id);
+  }
+
   pub fn free(&mut self, addr: MemoryUnit) {
     if let Some((addr, cell)) = self.stack.iter_mut().enumerate().nth(addr as usize) {
       *cell = Cell::Unused;
@@ -143,6 +188,20 @@ impl Generator {
     self.push(Token::Literal(r));
   }
 
+  pub fn stack_push(&mut self, sp: MemoryUnit, src: MemoryUnit) {
+    self.goto(src);
+    self.store(sp);
+    self.goto(sp);
+    self.add(1);
+  }
+
+  pub fn stack_pop(&mut self, sp: MemoryUnit, dst: MemoryUnit) {
+    self.goto(sp);
+    self.sub(1);
+    self.goto(dst);
+    self.deref(sp);
+  }
+
   pub fn reduce(&mut self) {
     self.push(Token::Exclamation);
     self.push(Token::Exclamation);
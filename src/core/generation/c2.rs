@@ -1,9 +1,41 @@
-use crate::core::{error::DSAsmError, generation::{Cell, Generator}, interpreter::MemoryUnit, parser::{BinaryOperator, Expr, Node, UnaryOperator}, tokenizer::Token};
+use crate::core::{error::{CompilerError, DSAsmError}, generation::{Cell, Generator}, interpreter::MemoryUnit, parser::{BinaryOperator, Expr, Node, UnaryOperator}, span::Spanned, tokenizer::Token};
 
 
 impl Generator {
-  pub fn generate_expr(&mut self, expr: &Expr) -> Result<MemoryUnit, DSAsmError> {
+  fn element_address(&mut self, id: u64, index: &Spanned<Expr>) -> Result<MemoryUnit, DSAsmError> {
+    let base = self.variable(id)?;
+    let len = self.stack.iter().filter(|cell| cell.is_variable_of_id(id)).count();
+    let index = self.generate_expr(index)?;
+    if self.bounds_checks {
+      let name = self.names.get(&id).cloned().unwrap_or_else(|| format!("_{}", id));
+      self.goto(index);
+      self.bounds(&name, len as MemoryUnit);
+    }
+    let ptr = self.alloc_temp()?;
+    self.clear(ptr);
+    self.add(base);
+    self.mem_add(ptr, index);
+    Ok(ptr)
+  }
+
+  fn field_address(&self, id: u64, offset: MemoryUnit) -> Result<MemoryUnit, DSAsmError> {
+    let base = self.variable(id)?;
+    let size = self.stack.iter().filter(|cell| cell.is_variable_of_id(id)).count();
+    if offset as usize >= size {
+      let name = self.names.get(&id).cloned().unwrap_or_else(|| format!("#{}", id));
+      return Err(CompilerError::FieldOutOfRange { name, offset, size, span: self.span.clone() }.into());
+    }
+    Ok(base + offset)
+  }
 
+  pub fn generate_expr(&mut self, expr: &Spanned<Expr>) -> Result<MemoryUnit, DSAsmError> {
+    let old = std::mem::replace(&mut self.span, expr.span.clone());
+    let ret = self.lower_expr(&expr.value);
+    self.span = old;
+    ret
+  }
+
+  fn lower_expr(&mut self, expr: &Expr) -> Result<MemoryUnit, DSAsmError> {
     match expr {
       Expr::Literal(l) => {
         let cell = self.alloc_temp()?;
@@ -13,9 +45,9 @@ impl Generator {
         Ok(cell)
       },
       Expr::Variable(id) => {
+        let ptr = self.variable(*id)?;
         let cell = self.alloc_temp()?;
-        let (ptr, _) = self.stack.iter().enumerate().find(|(_, cell)| cell.is_variable_of_id(*id)).unwrap();
-        self.copy(cell, ptr as MemoryUnit)?;
+        self.copy(cell, ptr)?;
         Ok(cell)
       },
       Expr::UserInput => {
@@ -26,28 +58,52 @@ impl Generator {
         Ok(cell)
       },
       Expr::Reference(ex) => {
-        match ex.as_ref() {
+        match &ex.value {
           Expr::Variable(id) => {
+            let ptr = self.variable(*id)?;
             let cell = self.alloc_temp()?;
-            let (ptr, _) = self.stack.iter().enumerate().find(|(_, cell)| cell.is_variable_of_id(*id)).unwrap();
             self.clear(cell);
             self.goto(cell);
-            self.add(ptr as MemoryUnit);
+            self.add(ptr);
             Ok(cell)
           },
           _ => {
-            return Err(DSAsmError::CompilerError(format!("Cannot reference an unstable address {}", ex)));
+            Err(CompilerError::UnstableReference { expr: ex.to_string(), span: ex.span.clone() }.into())
           }
         }
       },
+      Expr::Index(id, index) => {
+        let ptr = self.element_address(*id, index)?;
+        let cell = self.alloc_temp()?;
+        self.clear(cell);
+        self.goto(cell);
+        self.deref(ptr);
+        Ok(cell)
+      },
+      Expr::Field(id, offset) => {
+        let addr = self.field_address(*id, *offset)?;
+        let cell = self.alloc_temp()?;
+        self.copy(cell, addr)?;
+        Ok(cell)
+      },
+      Expr::Str(s) => {
+        let units: Vec<MemoryUnit> = s.chars().map(|ch| ch as MemoryUnit).collect();
+        let base = self.alloc_block(Cell::Used, units.len() + 1)?;
+        for (i, unit) in units.iter().enumerate() {
+          self.goto(base + i as MemoryUnit);
+          self.add(*unit);
+        }
+        let cell = self.alloc_temp()?;
+        self.clear(cell);
+        self.add(base);
+        Ok(cell)
+      },
       Expr::Dereference(ex) => {
-        let ex = self.generate_expr(&ex)?;
+        let ex = self.generate_expr(ex)?;
         let cell = self.alloc_temp()?;
         self.clear(cell);
         self.goto(cell);
-        self.push(Token::OpenSquare);
-        self.push(Token::Literal(ex));
-        self.push(Token::CloseSquare);
+        self.deref(ex);
         Ok(cell)
       },
       Expr::Unary(unary) => {
@@ -61,17 +117,21 @@ impl Generator {
           UnaryOperator::Bnot => {
             let temp = self.alloc_temp()?;
             self.clear(temp);
-            self.add(MemoryUnit::MAX);
+            self.add(self.machine.max());
             self.mem_sub(temp, expr);
             temp
           },
           UnaryOperator::Negate => {
+            let zero = self.new_label("negate_zero");
             let temp = self.alloc_temp()?;
             self.clear(temp);
-            self.add(MemoryUnit::MAX);
-            self.mem_sub(temp, expr);
+            self.goto(expr);
+            self.jze(&zero);
+            self.sub(1);
             self.goto(temp);
-            self.add(1);
+            self.add(self.machine.max());
+            self.mem_sub(temp, expr);
+            self.create_label(&zero);
             temp
           }
         };
@@ -104,16 +164,20 @@ impl Generator {
             self.div(right);
             right
           },
-          BinaryOperator::Equals => {
-            self.mem_sub(left, right);
+          BinaryOperator::Equals | BinaryOperator::NotEquals => {
+            let ltemp = self.alloc_temp()?;
+            self.copy(ltemp, left)?;
+            self.cmp(left, right);
+            self.cmp(right, ltemp);
             self.goto(left);
-            self.invert();
-            left
-          },
-          BinaryOperator::NotEquals => {
-            self.mem_sub(left, right);
+            self.sub(1);
+            self.goto(right);
+            self.sub(1);
             self.goto(left);
-            self.reduce();
+            self.mul(right);
+            if bin.operator == BinaryOperator::NotEquals {
+              self.invert();
+            }
             left
           },
           BinaryOperator::Greater => {
@@ -124,41 +188,23 @@ impl Generator {
             left
           },
           BinaryOperator::Less => {
-            let ltemp = self.alloc_temp()?;
-            let rtemp = self.alloc_temp()?;
-            self.copy(ltemp, left)?;
-            self.copy(rtemp, right)?;
-            self.mem_sub(ltemp, rtemp);
-            self.cmp(left, right);
-            self.goto(left);
-            self.sub(2);
+            self.cmp(right, left);
+            self.goto(right);
+            self.sub(1);
             self.invert();
-            self.mul(ltemp);
-            left
+            right
           },
           BinaryOperator::Lessequ => {
             self.cmp(left, right);
             self.goto(left);
-            self.sub(2);
-            self.invert();
+            self.sub(1);
             left
           },
           BinaryOperator::Grequ => {
-            let ltemp = self.alloc_temp()?;
-            let rtemp = self.alloc_temp()?;
-            self.copy(ltemp, left)?;
-            self.copy(rtemp, right)?;
-            self.mem_sub(ltemp, rtemp);
-            self.goto(ltemp);
-            self.invert();
-            self.cmp(left, right);
-            self.goto(left);
+            self.cmp(right, left);
+            self.goto(right);
             self.sub(1);
-            self.invert();
-            self.mem_add(left, ltemp);
-            self.goto(left);
-            self.reduce();
-            left
+            right
           },
           BinaryOperator::ShiftL => {
             self.goto(left);
@@ -177,18 +223,18 @@ impl Generator {
           BinaryOperator::Band => {
             let not_left = self.alloc_temp()?;
             self.clear(not_left);
-            self.add(MemoryUnit::MAX);
+            self.add(self.machine.max());
             self.mem_sub(not_left, left);
 
             let not_right = self.alloc_temp()?;
             self.clear(not_right);
-            self.add(MemoryUnit::MAX);
+            self.add(self.machine.max());
             self.mem_sub(not_right, right);
             self.or(not_left, not_right);
             
             let result = self.alloc_temp()?;
             self.clear(result);
-            self.add(MemoryUnit::MAX);
+            self.add(self.machine.max());
             self.mem_sub(result, not_left);
             result
           },
@@ -206,7 +252,7 @@ impl Generator {
             left
           },
           BinaryOperator::Or => {
-            self.mem_add(left, right);
+            self.or(left, right);
             self.goto(left);
             self.reduce();
             left
@@ -214,15 +260,19 @@ impl Generator {
         };
         Ok(result)
       },
-      Expr::MethodCall(_id, _params) => {
-        unimplemented!()
-      }
+      Expr::MethodCall(id, params) => self.method_call(*id, params)
     }
 
   }
 
-  pub fn generate(&mut self, node: &Node) -> Result<(), DSAsmError> {
+  pub fn generate(&mut self, node: &Spanned<Node>) -> Result<(), DSAsmError> {
+    let old = std::mem::replace(&mut self.span, node.span.clone());
+    let ret = self.lower(&node.value);
+    self.span = old;
+    ret
+  }
 
+  fn lower(&mut self, node: &Node) -> Result<(), DSAsmError> {
     match node {
       Node::Scope(scope) => {
         for ele in scope {
@@ -233,21 +283,260 @@ impl Generator {
         let cell = self.alloc()?;
         let ex = self.generate_expr(expr)?;
         self.copy(cell, ex)?;
-        self.stack[cell as usize] = Cell::Variable(*id);
+        self.bind(cell, *id);
         self.free_temps();
       },
       Node::VarSet(id, expr) => {
-        let (i, _) = self.stack.iter().enumerate().find(|(_, cell)| cell.is_variable_of_id(*id)).unwrap();
+        let addr = self.variable(*id)?;
         let ex = self.generate_expr(expr)?;
-        self.copy(i as MemoryUnit, ex)?;
+        self.copy(addr, ex)?;
         self.free_temps();
       },
-
-      _ => {
-        unimplemented!()
-      }
+      Node::ArrayDecl(id, len) => {
+        self.alloc_array(*id, *len)?;
+      },
+      Node::IndexSet(id, index, expr) => {
+        let ptr = self.element_address(*id, index)?;
+        let ex = self.generate_expr(expr)?;
+        self.goto(ex);
+        self.store(ptr);
+        self.free_temps();
+      },
+      Node::StructDecl(_) => { },
+      Node::StructVarDecl(id, size) => {
+        self.alloc_array(*id, *size)?;
+      },
+      Node::FieldSet(id, offset, expr) => {
+        let addr = self.field_address(*id, *offset)?;
+        let ex = self.generate_expr(expr)?;
+        self.copy(addr, ex)?;
+        self.free_temps();
+      },
+      Node::DerefSet(ptr, expr) => {
+        let ptr = self.generate_expr(ptr)?;
+        let ex = self.generate_expr(expr)?;
+        self.goto(ex);
+        self.store(ptr);
+        self.free_temps();
+      },
+      Node::If(cond, body, otherwise) => {
+        let skip = self.new_label("skip_if");
+        let cond = self.generate_expr(cond)?;
+        self.goto(cond);
+        self.jze(&skip);
+        self.free_temps();
+        self.generate(body)?;
+        if let Some(otherwise) = otherwise {
+          let end = self.new_label("end_if");
+          self.jump(&end);
+          self.create_label(&skip);
+          self.generate(otherwise)?;
+          self.create_label(&end);
+        } else {
+          self.create_label(&skip);
+        }
+      },
+      Node::While(cond, body) => {
+        let start = self.new_label("while");
+        let end = self.new_label("end_while");
+        self.create_label(&start);
+        let cond = self.generate_expr(cond)?;
+        self.goto(cond);
+        self.jze(&end);
+        self.free_temps();
+        self.generate(body)?;
+        self.jump(&start);
+        self.create_label(&end);
+      },
+      Node::Putchar(expr) => {
+        let cell = self.generate_expr(expr)?;
+        self.goto(cell);
+        self.putchar();
+        self.free_temps();
+      },
+      Node::Print(s) => {
+        let cell = self.alloc_temp()?;
+        self.clear(cell);
+        let mut current: MemoryUnit = 0;
+        for unit in s.chars().map(|ch| ch as MemoryUnit) {
+          if unit > current {
+            self.add(unit - current);
+          } else {
+            self.sub(current - unit);
+          }
+          self.putchar();
+          current = unit;
+        }
+        self.free_temps();
+      },
+      Node::ExprStmt(expr) => {
+        self.generate_expr(expr)?;
+        self.free_temps();
+      },
+      Node::MethodDecl(method) => self.method_decl(method)?,
+      Node::Return(expr) => self.method_return(expr)?,
+      Node::For(forloop) => {
+        let cell = self.alloc()?;
+        let ex = self.generate_expr(&forloop.start)?;
+        self.copy(cell, ex)?;
+        self.bind(cell, forloop.var.id);
+        self.free_temps();
+        let start = self.new_label("for");
+        let end = self.new_label("end_for");
+        self.create_label(&start);
+        let cond = self.generate_expr(&forloop.condition)?;
+        self.goto(cond);
+        self.jze(&end);
+        self.free_temps();
+        self.generate(&forloop.body)?;
+        self.generate(&forloop.increment)?;
+        self.jump(&start);
+        self.create_label(&end);
+        self.free(cell);
+      },
+      Node::Invalid => return Err(CompilerError::InvalidNode { span: self.span.clone() }.into())
     }
 
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::core::{error::{CompilerError, DSAsmError, InterpreterError, ParserError}, generation::{tests::{compile_for, run, run_checked, run_on, run_trapping}, Generator}, interpreter::Machine, parser::{Expr, Node}, span::{Span, Spanned}};
+
+  fn spanned<T>(value: T) -> Spanned<T> {
+    Spanned::new(value, Span::default())
+  }
+
+  #[test]
+  fn unallocated_variables_are_a_compiler_error() {
+    let nodes = vec![
+      vec![spanned(Node::VarSet(999, spanned(Expr::Literal(1))))],
+      vec![spanned(Node::ExprStmt(spanned(Expr::Variable(999))))],
+      vec![spanned(Node::ExprStmt(spanned(Expr::Index(999, Box::new(spanned(Expr::Literal(0)))))))],
+      vec![spanned(Node::IndexSet(999, spanned(Expr::Literal(0)), spanned(Expr::Literal(1))))],
+    ];
+    for program in nodes {
+      let result = Generator::new(program).with_names([(999, "ghost".to_string())].into()).generate_all();
+      assert!(matches!(result, Err(DSAsmError::Compiler(CompilerError::UndefinedVariable { ref name, .. })) if name == "ghost"), "{:?}", result);
+    }
+  }
+
+  #[test]
+  fn array_elements_are_read_and_written() {
+    let source = "let arr[4]; for (i = 0; i < 4; i = i + 1) arr[i] = 'a' + i; putchar(arr[3]); putchar(arr[0]); arr[1] = arr[2]; putchar(arr[1]);";
+    assert_eq!(run(source, "").unwrap(), "dac");
+    assert_eq!(run_checked(source, "").unwrap(), "dac");
+  }
+
+  #[test]
+  fn out_of_bounds_reads_are_caught() {
+    let result = run_checked("let arr[2]; let i = 2; putchar(arr[i]);", "");
+    assert!(matches!(result, Err(DSAsmError::Interpreter(InterpreterError::IndexOutOfBounds { index: 2, len: 2, .. }))), "{:?}", result);
+  }
+
+  #[test]
+  fn invalid_nodes_are_a_compiler_error() {
+    let result = Generator::new(vec![spanned(Node::Invalid)]).generate_all();
+    assert!(matches!(result, Err(DSAsmError::Compiler(CompilerError::InvalidNode { .. }))));
+  }
+
+  #[test]
+  fn nested_for_loops_keep_separate_counters() {
+    let source = "for (i = 0; i < 3; i = i + 1) for (j = 0; j < 2; j = j + 1) { putchar('a' + i); putchar('0' + j); }";
+    assert_eq!(run(source, "").unwrap(), "a0a1b0b1c0c1");
+  }
+
+  #[test]
+  fn inner_loop_reuses_its_counter_on_every_outer_iteration() {
+    let source = "for (i = 0; i < 2; i = i + 1) { for (j = 0; j < i + 1; j = j + 1) putchar('x'); putchar('0' + i); }";
+    assert_eq!(run(source, "").unwrap(), "x0xx1");
+  }
+
+  #[test]
+  fn globals_declared_after_a_method_do_not_share_its_frame() {
+    let source = "struct P { x, y } method gety(p: *P) { return p->y; } let pt: P; pt.y = 'K'; putchar(gety(&pt));";
+    assert_eq!(run(source, "").unwrap(), "K");
+  }
+
+  #[test]
+  fn out_of_bounds_index_names_the_array() {
+    let result = run_checked("let arr[3]; let i = 3; arr[i] = 1;", "");
+    assert!(matches!(result, Err(DSAsmError::Interpreter(InterpreterError::IndexOutOfBounds { ref name, index: 3, len: 3, .. })) if name == "arr"), "{:?}", result);
+  }
+
+  #[test]
+  fn in_bounds_indices_pass_the_check() {
+    assert_eq!(run_checked("let arr[3]; arr[2] = 'z'; putchar(arr[2]);", "").unwrap(), "z");
+  }
+
+  #[test]
+  fn comparisons_do_not_rely_on_wrap_around() {
+    let source = "putchar('0' + (3 <= 2)); putchar('0' + (2 <= 3)); putchar('0' + (3 <= 3)); putchar('0' + (2 >= 3)); putchar('0' + (3 >= 2)); putchar('0' + (3 >= 3));";
+    assert_eq!(run_trapping(source).unwrap(), "011011");
+    assert_eq!(run(source, "").unwrap(), "011011");
+  }
+
+  #[test]
+  fn negation_does_not_rely_on_wrap_around() {
+    let source = "let z = 0; let y = -z; putchar('a' + y); let one = 1; putchar('a' + (-one == 65535));";
+    assert_eq!(run_trapping(source).unwrap(), "ab");
+  }
+
+  #[test]
+  fn logical_or_does_not_rely_on_wrap_around() {
+    let source = "let m = 65535; putchar('0' + (m || m)); putchar('0' + (0 || 0));";
+    assert_eq!(run_trapping(source).unwrap(), "10");
+  }
+
+  #[test]
+  fn full_width_constants_are_emitted_as_a_single_step() {
+    let program = compile_for(Machine::new(32, 1024), "let x = -1; let y = ~x; let z = -y; let w = x & y;", false).unwrap();
+    assert!(program.len() < 1000, "{} instructions", program.len());
+  }
+
+  const FIB: &str = "method fib(n) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }";
+
+  #[test]
+  fn recursive_methods_keep_their_frames() {
+    let fact = "method fact(n) { if n < 2 { return 1; } return n * fact(n - 1); } putchar(fact(5));";
+    assert_eq!(run(fact, "").unwrap(), "x");
+    assert_eq!(run(&format!("{} putchar('0' + fib(7));", FIB), "").unwrap(), "=");
+  }
+
+  #[test]
+  fn deep_recursion_reports_a_call_stack_overflow() {
+    let source = format!("{} putchar(fib(10));", FIB);
+    let result = run_on(Machine::new(8, 255), &source);
+    assert!(matches!(result, Err(DSAsmError::Interpreter(InterpreterError::CallStackOverflow { .. }))), "{:?}", result);
+    assert_eq!(run_on(Machine::new(16, 1024), &source).unwrap(), "7");
+  }
+
+  #[test]
+  fn struct_fields_are_read_and_written() {
+    let source = "struct P { x, y } let a: P; let b: P; a.x = 'h'; a.y = 'i'; b.x = a.y; putchar(a.x); putchar(a.y); putchar(b.x);";
+    assert_eq!(run(source, "").unwrap(), "hii");
+  }
+
+  #[test]
+  fn struct_pointers_write_through_arrows() {
+    let source = "struct P { x, y } method set(p: *P) { p->y = 'o'; return p->x; } let a: P; a.x = 'n'; putchar(set(&a)); putchar(a.y);";
+    assert_eq!(run(source, "").unwrap(), "no");
+  }
+
+  #[test]
+  fn unknown_fields_are_rejected() {
+    let result = run("struct P { x } let a: P; a.z = 1;", "");
+    assert!(matches!(result, Err(DSAsmError::Parser(ParserError::UndefinedField { ref strukt, ref field, .. })) if strukt == "P" && field == "z"), "{:?}", result);
+  }
+
+  #[test]
+  fn fields_outside_of_the_struct_are_a_compiler_error() {
+    let nodes = vec![spanned(Node::StructVarDecl(7, 2)), spanned(Node::FieldSet(7, 2, spanned(Expr::Literal(1))))];
+    let result = Generator::new(nodes).generate_all();
+    assert!(matches!(result, Err(DSAsmError::Compiler(CompilerError::FieldOutOfRange { offset: 2, size: 2, .. }))), "{:?}", result);
+    let result = Generator::new(vec![spanned(Node::ExprStmt(spanned(Expr::Field(7, 0))))]).generate_all();
+    assert!(matches!(result, Err(DSAsmError::Compiler(CompilerError::UndefinedVariable { .. }))), "{:?}", result);
+  }
+}
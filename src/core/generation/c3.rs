@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+
+use crate::core::{error::{CompilerError, DSAsmError}, generation::{Cell, Frame, Generator, MethodInfo, Registers}, interpreter::MemoryUnit, parser::{Expr, Method}, span::Spanned};
+
+impl Generator {
+  fn registers(&mut self) -> Result<Registers, DSAsmError> {
+    if let Some(registers) = self.registers {
+      return Ok(registers);
+    }
+    let base = self.machine.memory - self.call_stack_size();
+    if self.stack[base..].iter().any(|cell| !cell.is_unused()) {
+      return Err(CompilerError::OutOfMemory { span: self.span.clone() }.into());
+    }
+    self.stack[base..].iter_mut().for_each(|cell| *cell = Cell::Used);
+    let registers = Registers { sp: self.alloc()?, ret: self.alloc()?, rid: self.alloc()? };
+    self.clear(registers.sp);
+    self.add(base as MemoryUnit);
+    self.registers = Some(registers);
+    Ok(registers)
+  }
+
+  // Faults before pushing `count` cells would run the stack pointer past the end of memory.
+  fn reserve(&mut self, sp: MemoryUnit, count: usize) -> Result<(), DSAsmError> {
+    let Some(limit) = self.machine.memory.checked_sub(count) else {
+      return Err(CompilerError::OutOfMemory { span: self.span.clone() }.into());
+    };
+    self.goto(sp);
+    self.stack_limit(limit as MemoryUnit);
+    Ok(())
+  }
+
+  pub fn method_decl(&mut self, method: &Method) -> Result<(), DSAsmError> {
+    let registers = self.registers()?;
+    let info = MethodInfo {
+      entry: self.new_label(&format!("{}_entry", method.name)),
+      dispatch: self.new_label(&format!("{}_dispatch", method.name)),
+      params: method.parameters.len(),
+      returns: Vec::new()
+    };
+    self.methods.insert(method.id, info.clone());
+    let skip = self.new_label("end_method");
+    let prologue = self.new_label("prologue");
+    let body = self.new_label("body");
+    let epilogue = self.new_label("epilogue");
+
+    self.jump(&skip);
+    self.create_label(&info.entry);
+    self.jump(&prologue);
+    self.create_label(&body);
+
+    let outer = self.frame.replace(Frame { epilogue: epilogue.clone(), touched: BTreeSet::new() });
+    let mut params: Vec<MemoryUnit> = Vec::new();
+    for param in &method.parameters {
+      let cell = self.alloc()?;
+      self.bind(cell, param.id);
+      params.push(cell);
+    }
+    self.generate(&method.body)?;
+    self.clear(registers.ret);
+    let frame = std::mem::replace(&mut self.frame, outer).unwrap_or(Frame { epilogue: epilogue.clone(), touched: BTreeSet::new() });
+    let cells: Vec<MemoryUnit> = frame.touched.into_iter().collect();
+
+    self.create_label(&epilogue);
+    cells.iter().rev().for_each(|cell| self.stack_pop(registers.sp, *cell));
+    self.stack_pop(registers.sp, registers.rid);
+    self.goto(registers.sp);
+    self.sub(info.params as MemoryUnit);
+    self.jump(&info.dispatch);
+
+    self.create_label(&prologue);
+    self.reserve(registers.sp, cells.len() + 1)?;
+    self.stack_push(registers.sp, registers.rid);
+    cells.iter().for_each(|cell| self.stack_push(registers.sp, *cell));
+    let depth = cells.len() + 1 + info.params;
+    for (i, param) in params.iter().enumerate() {
+      let offset = (depth - i) as MemoryUnit;
+      self.goto(registers.sp);
+      self.sub(offset);
+      self.goto(*param);
+      self.deref(registers.sp);
+      self.goto(registers.sp);
+      self.add(offset);
+    }
+    self.jump(&body);
+
+    cells.iter().for_each(|cell| self.stack[*cell as usize] = Cell::Frame);
+    self.create_label(&skip);
+    Ok(())
+  }
+
+  pub fn method_call(&mut self, id: u64, args: &[Spanned<Expr>]) -> Result<MemoryUnit, DSAsmError> {
+    let registers = self.registers()?;
+    let mut cells: Vec<MemoryUnit> = Vec::new();
+    for arg in args {
+      cells.push(self.generate_expr(arg)?);
+    }
+    self.reserve(registers.sp, cells.len())?;
+    cells.iter().for_each(|cell| self.stack_push(registers.sp, *cell));
+
+    let label = self.new_label("return");
+    let (entry, rid) = match self.methods.get_mut(&id) {
+      Some(info) => {
+        info.returns.push(label.clone());
+        (info.entry.clone(), info.returns.len())
+      },
+      None => return Err(CompilerError::UndefinedMethod { id, span: self.span.clone() }.into())
+    };
+    self.clear(registers.rid);
+    self.add(rid as MemoryUnit);
+    self.jump(&entry);
+    self.create_label(&label);
+
+    let result = self.alloc_temp()?;
+    self.r#move(result, registers.ret);
+    Ok(result)
+  }
+
+  pub fn method_return(&mut self, expr: &Spanned<Expr>) -> Result<(), DSAsmError> {
+    let epilogue = match &self.frame {
+      Some(frame) => frame.epilogue.clone(),
+      None => return Err(CompilerError::ReturnOutsideMethod { span: self.span.clone() }.into())
+    };
+    let registers = self.registers()?;
+    let value = self.generate_expr(expr)?;
+    self.r#move(registers.ret, value);
+    self.free_temps();
+    self.jump(&epilogue);
+    Ok(())
+  }
+
+  pub fn generate_dispatch(&mut self) {
+    let Some(registers) = self.registers else {
+      return;
+    };
+    let end = self.new_label("end");
+    self.jump(&end);
+    let methods: Vec<MethodInfo> = self.methods.values().cloned().collect();
+    for info in methods {
+      self.create_label(&info.dispatch);
+      self.goto(registers.rid);
+      for label in &info.returns {
+        self.sub(1);
+        self.jze(label);
+      }
+      self.jump(&end);
+    }
+    self.create_label(&end);
+  }
+}
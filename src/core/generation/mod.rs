@@ -1,4 +1,6 @@
-use crate::core::{error::DSAsmError, interpreter::{Interpreter, MemoryUnit}, parser::{Method, Node}, processor::{Processor, ProcessorInput}, tokenizer::Token};
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, fmt::Display};
+
+use crate::core::{error::DSAsmError, interpreter::{Machine, MemoryUnit}, parser::Node, processor::{Processor, ProcessorInput}, span::{Span, Spanned}, tokenizer::Token};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
 pub enum Cell {
@@ -7,34 +9,15 @@ pub enum Cell {
   Used,
   Variable(u64),
   Temporary,
+  Frame,
 }
 
 impl Cell {
   pub fn is_unused(&self) -> bool {
-    match self {
-      Self::Unused => true,
-      _ => false
-    }
-  }
-  pub fn to_string(&self) -> String {
-    match self {
-      Cell::Temporary => "Temp".into(),
-      Cell::Unused => "Unus".into(),
-      Cell::Used => "Used".into(),
-      Cell::Variable(id) => format!("#{:03}", id),
-    }
+    matches!(self, Self::Unused)
   }
-  pub fn is_used(&self) -> bool {
-    match self {
-      Self::Used => true,
-      _ => false
-    }
-  }
-  pub fn is_variable(&self) -> bool {
-    match self {
-      Self::Variable(_) => true,
-      _ => false
-    }
+  pub fn is_frame(&self) -> bool {
+    matches!(self, Self::Frame)
   }
   pub fn is_variable_of_id(&self, id: u64) -> bool {
     match self {
@@ -43,55 +26,133 @@ impl Cell {
     }
   }
   pub fn is_temp(&self) -> bool {
-    match self {
-      Self::Temporary => true,
-      _ => false
-    }
+    matches!(self, Self::Temporary)
   }
-  pub fn get_var_id(&self) -> Option<u64> {
+}
+
+impl Display for Cell {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
-      Cell::Variable(id) => Some(*id),
-      _ => None
+      Cell::Temporary => write!(f, "Temp"),
+      Cell::Unused => write!(f, "Unus"),
+      Cell::Used => write!(f, "Used"),
+      Cell::Frame => write!(f, "Fram"),
+      Cell::Variable(id) => write!(f, "#{:03}", id),
     }
-  } 
+  }
 }
 
-pub type Stack = [Cell; Interpreter::STACK_SIZE];
-
-const EMPTY_STACK: Stack = [Cell::Unused; Interpreter::STACK_SIZE];
+pub type Stack = Vec<Cell>;
 
 impl ProcessorInput for Node { }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+  pub sp: MemoryUnit,
+  pub ret: MemoryUnit,
+  pub rid: MemoryUnit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodInfo {
+  pub entry: String,
+  pub dispatch: String,
+  pub params: usize,
+  pub returns: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+  pub epilogue: String,
+  pub touched: BTreeSet<MemoryUnit>,
+}
+
 pub struct Generator {
   base: Processor<Node>,
+  machine: Machine,
   stack: Stack,
   pointer: MemoryUnit,
-  output: Vec<Token>,
+  output: Vec<Spanned<Token>>,
+  span: Span,
+  registers: Option<Registers>,
+  methods: BTreeMap<u64, MethodInfo>,
+  frame: Option<Frame>,
+  bounds_checks: bool,
+  symbols: BTreeMap<MemoryUnit, BTreeSet<u64>>,
+  names: HashMap<u64, String>,
 }
 
 impl Generator {
-  pub fn new(i: Vec<Node>) -> Generator {
-    Generator { base: Processor::new(i), stack: EMPTY_STACK, pointer: 0, output: Vec::new() }
+  pub fn new(i: Vec<Spanned<Node>>) -> Generator {
+    Generator {
+      base: Processor::spanned(i),
+      machine: Machine::default(),
+      stack: vec![Cell::Unused; Machine::default().memory],
+      pointer: 0,
+      output: Vec::new(),
+      span: Span::default(),
+      registers: None,
+      methods: BTreeMap::new(),
+      frame: None,
+      bounds_checks: false,
+      symbols: BTreeMap::new(),
+      names: HashMap::new()
+    }
+  }
+
+  pub fn with_machine(mut self, machine: Machine) -> Generator {
+    self.machine = machine;
+    self.stack = vec![Cell::Unused; machine.memory];
+    self
+  }
+
+  pub fn call_stack_size(&self) -> usize {
+    self.machine.memory / 4
+  }
+
+  pub fn with_bounds_checks(mut self, enabled: bool) -> Generator {
+    self.bounds_checks = enabled;
+    self
+  }
+
+  pub fn with_names(mut self, names: HashMap<u64, String>) -> Generator {
+    self.names = names;
+    self
   }
 
   pub fn print_memory(&self) {
-    let side: usize = (Interpreter::STACK_SIZE as f32).sqrt() as usize;
-    let hex_size_len: usize = std::mem::size_of::<MemoryUnit>() * 2 + 2;
+    let side: usize = (self.machine.memory as f32).sqrt().max(1.0) as usize;
+    let hex_size_len: usize = self.machine.bytes() * 2 + 2;
     self.stack.chunks(side).enumerate().for_each(|(addr, value)| {
       let temp: String = value.iter().map(|u: &Cell| format!("{:^4}", u.to_string())).collect::<Vec<String>>().join(" | ") + " |";
       println!("{:#0hex_size_len$X} | {}", addr * value.len(), temp);
     });
   }
 
+  pub fn symbols(&self) -> HashMap<MemoryUnit, String> {
+    let mut bases: HashMap<u64, MemoryUnit> = HashMap::new();
+    self.symbols.iter().for_each(|(addr, ids)| ids.iter().for_each(|id| { bases.entry(*id).or_insert(*addr); }));
+    let sized: BTreeSet<u64> = bases.keys().copied().filter(|id| self.symbols.iter().filter(|(_, ids)| ids.contains(id)).count() > 1).collect();
+    self.symbols.iter().map(|(addr, ids)| {
+      let mut labels: Vec<String> = ids.iter().map(|id| {
+        let name = self.names.get(id).cloned().unwrap_or_else(|| format!("#{}", id));
+        if sized.contains(id) { format!("{}[{}]", name, addr - bases[id]) } else { name }
+      }).collect();
+      labels.dedup();
+      (*addr, labels.join("/"))
+    }).collect()
+  }
+
   fn push(&mut self, t: Token) {
-    self.output.push(t);
+    self.output.push(Spanned::new(t, self.span.clone()));
   }
 
-  pub fn generate_all(&mut self) -> Result<Vec<Token>, DSAsmError> {
+  pub fn generate_all(&mut self) -> Result<Vec<Spanned<Token>>, DSAsmError> {
     while self.base.has_peek() {
-      let node = self.base.consume();
+      let node = Spanned::new(self.base.consume(), self.base.prev_span());
       self.generate(&node)?;
     }
+    self.generate_dispatch();
 
     Ok(self.output.clone())
   }
@@ -100,3 +161,41 @@ impl Generator {
 mod c0;
 mod c1;
 mod c2;
+mod c3;
+
+#[cfg(test)]
+mod tests {
+  use crate::core::{bytecode::{BytecodeConverter, Instruction}, error::DSAsmError, generation::Generator, interpreter::{self, Arithmetic, Interpreter, Machine}, parser::Parser, span::Spanned, tokenizer::Tokenizer};
+
+  pub(crate) fn run(source: &str, input: &str) -> Result<String, DSAsmError> {
+    interpreter::run_with_input(compile(source, false)?, input)
+  }
+
+  pub(crate) fn run_checked(source: &str, input: &str) -> Result<String, DSAsmError> {
+    interpreter::run_with_input(compile(source, true)?, input)
+  }
+
+  pub(crate) fn run_trapping(source: &str) -> Result<String, DSAsmError> {
+    let mut interpreter = Interpreter::new(compile(source, false)?, "".as_bytes(), Vec::new()).with_arithmetic(Arithmetic::Trapping);
+    interpreter.interpret()?;
+    Ok(String::from_utf8_lossy(&interpreter.into_output()).into_owned())
+  }
+
+  pub(crate) fn run_on(machine: Machine, source: &str) -> Result<String, DSAsmError> {
+    let mut interpreter = Interpreter::new(compile_for(machine, source, false)?, "".as_bytes(), Vec::new()).with_machine(machine);
+    interpreter.interpret()?;
+    Ok(String::from_utf8_lossy(&interpreter.into_output()).into_owned())
+  }
+
+  fn compile(source: &str, bounds: bool) -> Result<Vec<Spanned<Instruction>>, DSAsmError> {
+    compile_for(Machine::default(), source, bounds)
+  }
+
+  pub(crate) fn compile_for(machine: Machine, source: &str, bounds: bool) -> Result<Vec<Spanned<Instruction>>, DSAsmError> {
+    let tokens = Tokenizer::new(source.chars().collect(), "test").with_machine(machine).tokenize()?;
+    let mut parser = Parser::new(tokens).with_machine(machine);
+    let nodes = parser.parse_all()?;
+    let tokens = Generator::new(nodes).with_machine(machine).with_bounds_checks(bounds).with_names(parser.symbols().clone()).generate_all()?;
+    BytecodeConverter::new(tokens).convert()
+  }
+}
@@ -1,158 +1,463 @@
-use std::{collections::HashMap, io::{Read, Write, stdin, stdout}};
+use std::{io::{self, ErrorKind, Read, Stdin, Stdout, Write}, time::{Duration, Instant}};
 
-use crate::core::{bytecode::Instruction, error::DSAsmError, processor::{Processor, ProcessorInput}};
+use crate::core::{bytecode::{Instruction, Target}, error::{DSAsmError, InterpreterError}, processor::{Processor, ProcessorInput}, span::Spanned};
 
-pub type MemoryUnit = u16;
+pub type MemoryUnit = u32;
 
-pub struct Interpreter {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Machine {
+  pub bits: u32,
+  pub memory: usize,
+}
+
+impl Default for Machine {
+  fn default() -> Machine {
+    Machine { bits: 16, memory: 1024 }
+  }
+}
+
+impl Machine {
+  pub const WORD_SIZES: [u32; 3] = [8, 16, 32];
+  pub const MAX_MEMORY: usize = 1 << 20;
+
+  pub fn new(bits: u32, memory: usize) -> Machine {
+    Machine { bits, memory }
+  }
+
+  pub fn max(&self) -> MemoryUnit {
+    ((1u64 << self.bits) - 1) as MemoryUnit
+  }
+
+  pub fn bytes(&self) -> usize {
+    self.bits as usize / 8
+  }
+
+  pub fn has_valid_bits(&self) -> bool {
+    Machine::WORD_SIZES.contains(&self.bits)
+  }
+
+  // Every address has to fit in a cell, and memory is capped so that it can
+  // always be allocated up front.
+  pub fn max_memory(&self) -> usize {
+    (self.max() as usize).min(Machine::MAX_MEMORY)
+  }
+
+  pub fn has_valid_memory(&self) -> bool {
+    self.memory > 0 && self.memory <= self.max_memory()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofPolicy {
+  Unchanged,
+  Zero,
+  Max,
+  #[default]
+  Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+  Instructions(u64),
+  Time(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Arithmetic {
+  #[default]
+  Wrapping,
+  Trapping,
+}
+
+pub struct Interpreter<R: Read = Stdin, W: Write = Stdout> {
   base: Processor<Instruction>,
-  stack: [MemoryUnit; Interpreter::STACK_SIZE],
+  input: R,
+  output: W,
+  eof: EofPolicy,
+  arithmetic: Arithmetic,
+  max_instructions: Option<u64>,
+  timeout: Option<Duration>,
+  machine: Machine,
+  stack: Vec<MemoryUnit>,
   stack_ptr: usize,
-  labels: HashMap<String, usize>
+  returns: Vec<usize>
 }
 
 impl ProcessorInput for Instruction { }
 
 impl Interpreter {
-  const MAX_STACK_SIZE: usize = 1024;
-  pub const STACK_SIZE: usize = {
-    let max = MemoryUnit::MAX as usize;
-    if max > Interpreter::MAX_STACK_SIZE { Interpreter::MAX_STACK_SIZE } else { max }
-  };
-  pub fn new(content: Vec<Instruction>) -> Interpreter {
+  pub const RETURN_STACK_SIZE: usize = 256;
+  const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+}
+
+pub fn run_with_input(content: Vec<Spanned<Instruction>>, input: &str) -> Result<String, DSAsmError> {
+  let mut interpreter = Interpreter::new(content, input.as_bytes(), Vec::new());
+  interpreter.interpret()?;
+  Ok(String::from_utf8_lossy(&interpreter.into_output()).into_owned())
+}
+
+impl<R: Read, W: Write> Interpreter<R, W> {
+  pub fn new(content: Vec<Spanned<Instruction>>, input: R, output: W) -> Interpreter<R, W> {
     Interpreter { 
-      base: Processor::new(content),
-      stack: [0; Interpreter::STACK_SIZE],
+      base: Processor::spanned(content),
+      input,
+      output,
+      eof: EofPolicy::default(),
+      arithmetic: Arithmetic::default(),
+      max_instructions: None,
+      timeout: None,
+      machine: Machine::default(),
+      stack: vec![0; Machine::default().memory],
       stack_ptr: 0,
-      labels: HashMap::new()
+      returns: Vec::new()
     }
   }
 
   pub fn print_memory(&self) {
-    let side: usize = (Interpreter::STACK_SIZE as f32).sqrt() as usize;
+    let side: usize = (self.machine.memory as f32).sqrt().max(1.0) as usize;
     let maximum: &MemoryUnit = self.stack.iter().max().unwrap();
     let digits: usize = maximum.to_string().len();
-    let hex_size_len: usize = std::mem::size_of::<MemoryUnit>() * 2 + 2;
+    let hex_size_len: usize = self.machine.bytes() * 2 + 2;
     self.stack.chunks(side).enumerate().for_each(|(addr, value)| {
       let temp: String = value.iter().map(|u| format!("{:0digits$}", u)).collect::<Vec<String>>().join(" | ") + " |";
       println!("{:#0hex_size_len$X} | {}", addr * value.len(), temp);
     });
   }
 
-  fn label_must_exist(&self, name: &str) -> Result<(), DSAsmError> {
-    if !self.labels.contains_key(&name.to_string()) {
-      return Err(DSAsmError::InterpreterError(format!("Label '{}' does not exists", &name)).into());
+  pub fn with_machine(mut self, machine: Machine) -> Self {
+    self.machine = machine;
+    self.stack = vec![0; machine.memory];
+    self
+  }
+
+  pub fn with_eof(mut self, eof: EofPolicy) -> Self {
+    self.eof = eof;
+    self
+  }
+
+  pub fn with_arithmetic(mut self, arithmetic: Arithmetic) -> Self {
+    self.arithmetic = arithmetic;
+    self
+  }
+
+  pub fn with_max_instructions(mut self, max_instructions: u64) -> Self {
+    self.max_instructions = Some(max_instructions);
+    self
+  }
+
+  pub fn with_timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  pub fn into_output(self) -> W {
+    self.output
+  }
+
+  fn ip(&self) -> usize {
+    self.base.get_peek().saturating_sub(1)
+  }
+
+  fn io_error(&self, e: io::Error) -> DSAsmError {
+    InterpreterError::Io { kind: e.kind(), ip: self.ip(), span: self.base.prev_span() }.into()
+  }
+
+  fn exhausted(&self, budget: Budget) -> DSAsmError {
+    let ip = self.base.get_peek();
+    InterpreterError::BudgetExhausted { budget, ip, memory: self.stack.to_vec(), span: self.base.span_at(ip) }.into()
+  }
+
+  fn cell(&self, addr: MemoryUnit) -> Result<usize, DSAsmError> {
+    if addr as usize >= self.machine.memory {
+      return Err(InterpreterError::InvalidAddress { addr, memory: self.machine.memory, ip: self.ip(), span: self.base.prev_span() }.into());
+    }
+    Ok(addr as usize)
+  }
+
+  fn overflow(&self, instruction: Instruction, left: MemoryUnit, right: MemoryUnit) -> DSAsmError {
+    InterpreterError::ArithmeticOverflow { instruction, left, right, ip: self.ip(), span: self.base.prev_span() }.into()
+  }
+
+  fn checked(&self, instruction: Instruction, left: MemoryUnit, right: MemoryUnit, value: u64) -> Result<MemoryUnit, DSAsmError> {
+    let max = self.machine.max();
+    if value > max as u64 && self.arithmetic == Arithmetic::Trapping {
+      return Err(self.overflow(instruction, left, right));
+    }
+    Ok(value as MemoryUnit & max)
+  }
+
+  fn shifted(&self, instruction: Instruction, value: MemoryUnit, amount: MemoryUnit, shift: fn(u64, u32) -> u64) -> Result<MemoryUnit, DSAsmError> {
+    if amount >= self.machine.bits && self.arithmetic == Arithmetic::Trapping {
+      return Err(self.overflow(instruction, value, amount));
+    }
+    Ok(shift(value as u64, amount % self.machine.bits) as MemoryUnit & self.machine.max())
+  }
+
+  fn validate(&self) -> Result<(), DSAsmError> {
+    let mut ip = 0;
+    while let Some(ins) = self.base.get(ip) {
+      if let Some(target) = ins.target() {
+        match self.base.get(target.ip) {
+          Some(Instruction::Label(name)) if *name == target.name => { },
+          _ => return Err(InterpreterError::UndefinedLabel { name: target.name.clone(), ip, span: self.base.span_at(ip) }.into())
+        }
+      }
+      ip += 1;
     }
     Ok(())
   }
 
   pub fn interpret(&mut self) -> Result<(), DSAsmError> {
+    self.validate()?;
+    let start = Instant::now();
+    let max_instructions = self.max_instructions.unwrap_or(u64::MAX);
+    let interval = if self.timeout.is_some() { Interpreter::TIMEOUT_CHECK_INTERVAL } else { u64::MAX };
+    let mut checkpoint = interval.min(max_instructions);
+    let mut steps: u64 = 0;
     while self.base.has_peek() {
-      match self.base.consume() {
-        Instruction::Label(name) => {
-          if self.labels.contains_key(&name) {
-            return Err(DSAsmError::InterpreterError(format!("Label '{}' already exists", &name)).into());
-          }
-          self.labels.insert(name, self.base.get_peek());
-        },
-        _ => { }
+      if steps >= checkpoint {
+        if steps >= max_instructions {
+          return Err(self.exhausted(Budget::Instructions(max_instructions)));
+        }
+        if let Some(timeout) = self.timeout && start.elapsed() >= timeout {
+          return Err(self.exhausted(Budget::Time(timeout)));
+        }
+        checkpoint = steps.saturating_add(interval).min(max_instructions);
       }
-    }
-    self.base.set_peek(0);
-    while self.base.has_peek() {
-      match self.base.consume() {
-        Instruction::MoveStack(addr) => {
-          if addr as usize >= Interpreter::STACK_SIZE {
-            return Err(DSAsmError::InterpreterError(format!("Invalid address {}", addr)).into())
-          }
-          self.stack_ptr = addr as usize;
+      steps += 1;
+      let ip = self.base.get_peek();
+      self.base.set_peek(ip + 1);
+      match self.base.get(ip) {
+        Some(&Instruction::MoveStack(addr)) => {
+          self.stack_ptr = self.cell(addr)?;
         },
-        Instruction::Increment(amount) => {
+        Some(&Instruction::Increment(amount)) => {
           let tmp: MemoryUnit = self.stack[self.stack_ptr];
-          self.stack[self.stack_ptr] = tmp.wrapping_add(amount);
+          self.stack[self.stack_ptr] = self.checked(Instruction::Increment(amount), tmp, amount, tmp as u64 + amount as u64)?;
         },
-        Instruction::Decrement(amount) => {
+        Some(&Instruction::Decrement(amount)) => {
           let tmp: MemoryUnit = self.stack[self.stack_ptr];
-          self.stack[self.stack_ptr] = tmp.wrapping_sub(amount);
+          self.stack[self.stack_ptr] = self.checked(Instruction::Decrement(amount), tmp, amount, (tmp as u64).wrapping_sub(amount as u64))?;
         },
-        Instruction::UserInput => {
-          stdout().flush().ok();
+        Some(Instruction::UserInput) => {
           let mut buf: [u8; 1] = [0];
-          stdin().read_exact(&mut buf).expect("Cannot read user input");
-          self.stack[self.stack_ptr] = buf[0] as MemoryUnit;
+          match self.output.flush().and_then(|_| self.input.read_exact(&mut buf)) {
+            Ok(()) => self.stack[self.stack_ptr] = buf[0] as MemoryUnit,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => match self.eof {
+              EofPolicy::Unchanged => { },
+              EofPolicy::Zero => self.stack[self.stack_ptr] = 0,
+              EofPolicy::Max => self.stack[self.stack_ptr] = self.machine.max(),
+              EofPolicy::Error => return Err(InterpreterError::EndOfInput { ip: self.ip(), span: self.base.prev_span() }.into())
+            },
+            Err(e) => return Err(self.io_error(e))
+          }
         },
-        Instruction::Print => {
-          print!("{}", (self.stack[self.stack_ptr] as u8) as char);
+        Some(Instruction::Print) => {
+          let mut buf: [u8; 4] = [0; 4];
+          let ch = ((self.stack[self.stack_ptr] as u8) as char).encode_utf8(&mut buf);
+          if let Err(e) = self.output.write_all(ch.as_bytes()) {
+            return Err(self.io_error(e));
+          }
         },
-        Instruction::Label(_) => { },
-        Instruction::Jump(name) => {
-          self.label_must_exist(&name)?;
-          self.base.set_peek(self.labels[&name]);
+        Some(Instruction::Label(_)) => { },
+        Some(&Instruction::Jump(Target { ip, .. })) => {
+          self.base.set_peek(ip);
         },
-        Instruction::JumpZero(name) => {
-          self.label_must_exist(&name)?;
+        Some(&Instruction::JumpZero(Target { ip, .. })) => {
           if self.stack[self.stack_ptr] == 0 {
-            self.base.set_peek(self.labels[&name]);
+            self.base.set_peek(ip);
           }
         },
-        Instruction::JumpNotZero(name) => {
-          self.label_must_exist(&name)?;
+        Some(&Instruction::JumpNotZero(Target { ip, .. })) => {
           if self.stack[self.stack_ptr] != 0 {
-            self.base.set_peek(self.labels[&name]);
+            self.base.set_peek(ip);
           }
         },
-        Instruction::Invert => {
+        Some(Instruction::Invert) => {
           if self.stack[self.stack_ptr] == 0 {
             self.stack[self.stack_ptr] = 1;
           } else {
             self.stack[self.stack_ptr] = 0;
           };
         },
-        Instruction::Multiply(addr) => {
+        Some(&Instruction::Multiply(addr)) => {
           let a = self.stack[self.stack_ptr];
-          let b = self.stack[addr as usize];
-          self.stack[self.stack_ptr] = a * b;
+          let b = self.stack[self.cell(addr)?];
+          self.stack[self.stack_ptr] = self.checked(Instruction::Multiply(addr), a, b, a as u64 * b as u64)?;
         },
-        Instruction::Divide(addr) => {
+        Some(&Instruction::Divide(addr)) => {
+          let addr = self.cell(addr)?;
           let a = self.stack[self.stack_ptr];
-          let b = self.stack[addr as usize];
+          let b = self.stack[addr];
+          if b == 0 {
+            return Err(InterpreterError::DivisionByZero { dividend: a, ip: self.ip(), span: self.base.prev_span() }.into());
+          }
           self.stack[self.stack_ptr] = a / b;
-          self.stack[addr as usize] = a % b
+          self.stack[addr] = a % b
         },
-        Instruction::Clear => {
+        Some(Instruction::Clear) => {
           self.stack[self.stack_ptr] = 0;
         },
-        Instruction::Dereference(addr) => {
-          let addr = self.stack[addr as usize];
-          self.stack[self.stack_ptr] = self.stack[addr as usize];
+        Some(&Instruction::Dereference(addr)) => {
+          let addr = self.cell(self.stack[self.cell(addr)?])?;
+          self.stack[self.stack_ptr] = self.stack[addr];
         },
-        Instruction::Goto(ip) => {
+        Some(&Instruction::Store(addr)) => {
+          let target = self.cell(self.stack[self.cell(addr)?])?;
+          self.stack[target] = self.stack[self.stack_ptr];
+        },
+        Some(&Instruction::Goto(ip)) => {
           self.base.set_peek(ip as usize);
         },
-        Instruction::Compare(addr) => {
+        Some(&Instruction::Call(Target { ip, .. })) => {
+          if self.returns.len() >= Interpreter::RETURN_STACK_SIZE {
+            return Err(InterpreterError::ReturnStackOverflow { ip: self.ip(), span: self.base.prev_span() }.into());
+          }
+          self.returns.push(self.base.get_peek());
+          self.base.set_peek(ip);
+        },
+        Some(Instruction::Ret) => {
+          match self.returns.pop() {
+            Some(ip) => self.base.set_peek(ip),
+            None => return Err(InterpreterError::ReturnStackUnderflow { ip: self.ip(), span: self.base.prev_span() }.into())
+          }
+        },
+        Some(&Instruction::Compare(addr)) => {
           let left = self.stack[self.stack_ptr];
-          let right = self.stack[addr as usize];
+          let right = self.stack[self.cell(addr)?];
           let value = if left > right {1} else {2};
           self.stack[self.stack_ptr] = value;
             
         },
-        Instruction::ShiftL(addr) => {
-          let temp = self.stack[addr as usize] % (std::mem::size_of::<MemoryUnit>() * 8) as MemoryUnit;
-          self.stack[self.stack_ptr] = self.stack[self.stack_ptr] << temp;
+        Some(&Instruction::ShiftL(addr)) => {
+          let a = self.stack[self.stack_ptr];
+          let b = self.stack[self.cell(addr)?];
+          self.stack[self.stack_ptr] = self.shifted(Instruction::ShiftL(addr), a, b, |v, n| v << n)?;
+        },
+        Some(&Instruction::ShiftR(addr)) => {
+          let a = self.stack[self.stack_ptr];
+          let b = self.stack[self.cell(addr)?];
+          self.stack[self.stack_ptr] = self.shifted(Instruction::ShiftR(addr), a, b, |v, n| v >> n)?;
         },
-        Instruction::ShiftR(addr) => {
-          let temp = self.stack[addr as usize] % (std::mem::size_of::<MemoryUnit>() * 8) as MemoryUnit;
-          self.stack[self.stack_ptr] = self.stack[self.stack_ptr] >> temp;
+        Some(&Instruction::Or(addr)) => {
+          self.stack[self.stack_ptr] |= self.stack[self.cell(addr)?];
         },
-        Instruction::Or(addr) => {
-          self.stack[self.stack_ptr] = self.stack[self.stack_ptr] | self.stack[addr as usize];
+        Some(Instruction::Bounds(name, len)) => {
+          let index = self.stack[self.stack_ptr];
+          if index >= *len {
+            return Err(InterpreterError::IndexOutOfBounds { name: name.clone(), index, len: *len, ip: self.ip(), span: self.base.prev_span() }.into());
+          }
         },
-        t => {
-          return Err(DSAsmError::InterpreterError(format!("Unexpected Instruction '{}'", t)).into());
-        }
+        Some(&Instruction::StackLimit(limit)) => {
+          let sp = self.stack[self.stack_ptr];
+          if sp > limit {
+            return Err(InterpreterError::CallStackOverflow { sp, limit, ip: self.ip(), span: self.base.prev_span() }.into());
+          }
+        },
+        Some(instruction) => {
+          return Err(InterpreterError::UnexpectedInstruction { instruction: instruction.clone(), ip: self.ip(), span: self.base.prev_span() }.into());
+        },
+        None => break
       }
     }
 
-    Ok(())
+    self.output.flush().map_err(|e| self.io_error(e))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use crate::core::{bytecode::{BytecodeConverter, Instruction}, error::{DSAsmError, InterpreterError}, interpreter::{self, Budget, EofPolicy, Interpreter, Machine, MemoryUnit}, span::Spanned, tokenizer::Tokenizer};
+
+  fn asm(source: &str) -> Vec<Spanned<Instruction>> {
+    let tokens = Tokenizer::new(source.chars().collect(), "test").tokenize().unwrap();
+    BytecodeConverter::new(tokens).convert().unwrap()
+  }
+
+  #[test]
+  fn print_writes_the_current_cell() {
+    assert_eq!(interpreter::run_with_input(asm("+72 . +33 . ~ +10 ."), "").unwrap(), "Hi\n");
+  }
+
+  #[test]
+  fn user_input_reads_one_byte_at_a_time() {
+    assert_eq!(interpreter::run_with_input(asm(", +1 . ^1 , +1 ."), "ab").unwrap(), "bc");
+  }
+
+  #[test]
+  fn reading_past_the_end_of_input_is_an_error_by_default() {
+    let result = interpreter::run_with_input(asm(", . ,"), "x");
+    assert!(matches!(result, Err(DSAsmError::Interpreter(InterpreterError::EndOfInput { ip: 2, .. }))));
+  }
+
+  #[test]
+  fn eof_policy_decides_what_is_stored() {
+    let run = |eof: EofPolicy| {
+      let mut interpreter = Interpreter::new(asm("+65 , ."), "".as_bytes(), Vec::new()).with_eof(eof);
+      interpreter.interpret().unwrap();
+      interpreter.into_output()
+    };
+    assert_eq!(run(EofPolicy::Unchanged), b"A");
+    assert_eq!(run(EofPolicy::Zero), b"\0");
+    assert_eq!(run(EofPolicy::Max), "\u{FF}".as_bytes());
+  }
+
+  #[test]
+  fn memory_is_capped_at_an_allocatable_size() {
+    assert!(Machine::new(8, 255).has_valid_memory());
+    assert!(!Machine::new(8, 256).has_valid_memory());
+    assert!(Machine::new(32, Machine::MAX_MEMORY).has_valid_memory());
+    assert!(!Machine::new(32, 4_000_000_000).has_valid_memory());
+  }
+
+  #[test]
+  fn instruction_budget_stops_endless_loops() {
+    let mut interpreter = Interpreter::new(asm(":loop + @loop"), "".as_bytes(), Vec::new()).with_machine(Machine::new(16, 4)).with_max_instructions(10);
+    let result = interpreter.interpret();
+    assert!(matches!(result, Err(DSAsmError::Interpreter(InterpreterError::BudgetExhausted { budget: Budget::Instructions(10), ip: 1, ref memory, .. })) if *memory == [3, 0, 0, 0]), "{:?}", result);
+  }
+
+  #[test]
+  fn timeout_stops_endless_loops() {
+    let timeout = Duration::from_millis(20);
+    let mut interpreter = Interpreter::new(asm(":loop + @loop"), "".as_bytes(), Vec::new()).with_machine(Machine::new(32, 4)).with_timeout(timeout);
+    match interpreter.interpret() {
+      Err(DSAsmError::Interpreter(InterpreterError::BudgetExhausted { budget, ip, memory, .. })) => {
+        assert_eq!(budget, Budget::Time(timeout));
+        assert!(ip < 3);
+        assert_eq!(memory.len(), 4);
+        assert!(memory[0] > 0 && memory[1..] == [0, 0, 0]);
+      },
+      result => panic!("expected the timeout to expire, got {:?}", result)
+    }
+  }
+
+  #[test]
+  fn programs_within_budget_finish() {
+    let mut interpreter = Interpreter::new(asm("+65 ."), "".as_bytes(), Vec::new()).with_max_instructions(2).with_timeout(Duration::from_secs(10));
+    interpreter.interpret().unwrap();
+    assert_eq!(interpreter.into_output(), b"A");
+  }
+
+  #[test]
+  fn calls_return_to_the_instruction_after_the_call() {
+    let source = "&a . &b . @end :a +65 &b ; :b +1 ; :end";
+    assert_eq!(interpreter::run_with_input(asm(source), "").unwrap(), "BC");
+  }
+
+  #[test]
+  fn returning_without_a_caller_is_an_error() {
+    let result = interpreter::run_with_input(asm("+ ;"), "");
+    assert!(matches!(result, Err(DSAsmError::Interpreter(InterpreterError::ReturnStackUnderflow { ip: 1, .. }))), "{:?}", result);
+  }
+
+  #[test]
+  fn nesting_calls_past_the_return_stack_is_an_error() {
+    let mut interpreter = Interpreter::new(asm(":f + &f"), "".as_bytes(), Vec::new());
+    let result = interpreter.interpret();
+    assert!(matches!(result, Err(DSAsmError::Interpreter(InterpreterError::ReturnStackOverflow { ip: 2, .. }))), "{:?}", result);
+    assert_eq!(interpreter.stack[0], Interpreter::RETURN_STACK_SIZE as MemoryUnit + 1);
   }
 }
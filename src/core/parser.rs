@@ -1,11 +1,36 @@
-use std::{fmt::Display, sync::atomic::{AtomicU64, Ordering}};
+use std::{collections::HashMap, fmt::Display, sync::atomic::{AtomicU64, Ordering}};
 
-use crate::core::{error::DSAsmError, interpreter::MemoryUnit, processor::Processor, tokenizer::Token};
+use crate::core::{error::{DSAsmError, ParserError}, interpreter::{Machine, MemoryUnit}, processor::Processor, span::{Span, Spanned}, tokenizer::Token};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Variable {
   pub name: String,
   pub id: u64,
+  pub kind: VarKind,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VarKind {
+  #[default]
+  Scalar,
+  Array(MemoryUnit),
+  Struct(u64),
+  Pointer(u64),
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Struct {
+  pub name: String,
+  pub id: u64,
+  pub fields: Vec<String>,
+}
+
+impl Struct {
+  pub fn size(&self) -> MemoryUnit {
+    self.fields.len() as MemoryUnit
+  }
+
+  pub fn offset(&self, field: &str) -> Option<MemoryUnit> {
+    self.fields.iter().position(|f| f == field).map(|i| i as MemoryUnit)
+  }
 }
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BinaryOperator {
@@ -80,13 +105,13 @@ pub enum UnaryOperator {
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Binary {
-  pub left: Box<Expr>,
-  pub right: Box<Expr>,
+  pub left: Box<Spanned<Expr>>,
+  pub right: Box<Spanned<Expr>>,
   pub operator: BinaryOperator 
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Unary {
-  pub right: Box<Expr>,
+  pub right: Box<Spanned<Expr>>,
   pub operator: UnaryOperator
 }
 
@@ -95,9 +120,12 @@ pub enum Expr {
   Literal(MemoryUnit),
   Variable(u64),
   UserInput,
-  Reference(Box<Expr>),
-  Dereference(Box<Expr>),
-  MethodCall(u64, Vec<Expr>),
+  Reference(Box<Spanned<Expr>>),
+  Dereference(Box<Spanned<Expr>>),
+  MethodCall(u64, Vec<Spanned<Expr>>),
+  Index(u64, Box<Spanned<Expr>>),
+  Field(u64, MemoryUnit),
+  Str(String),
   Binary(Binary),
   Unary(Unary),
 }
@@ -110,8 +138,11 @@ impl Display for Expr  {
       Self::Dereference(e) => write!(f, "*{}", e),
       Self::Reference(e) => write!(f, "&{}", e),
       Self::Unary(un) => write!(f, "{}{}", un.operator, un.right),
-      Self::Binary(bin) => write!(f, "{} {} {}", bin.left, bin.operator, bin.right),
+      Self::Binary(bin) => write!(f, "({} {} {})", bin.left, bin.operator, bin.right),
       Self::UserInput => write!(f, "getchar"),
+      Self::Index(id, index) => write!(f, "${{#{}}}[{}]", id, index),
+      Self::Field(id, offset) => write!(f, "${{#{}}}.{}", id, offset),
+      Self::Str(s) => write!(f, "{:?}", s),
       Self::MethodCall(id, params) => write!(f, "${{#{}}}({})", id, { let mut tmp: Vec<String> = Vec::new(); params.iter().for_each(|e| tmp.push(format!("{}", e))); tmp.join(", ")})
     }
   }
@@ -119,24 +150,32 @@ impl Display for Expr  {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ForLoop {
-  var_name: String,
-  start: Expr,
-  condition: Expr,
-  increment: Box<Node>,
-  body: Box<Node>
+  pub var: Variable,
+  pub start: Spanned<Expr>,
+  pub condition: Spanned<Expr>,
+  pub increment: Box<Spanned<Node>>,
+  pub body: Box<Spanned<Node>>
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub enum Node {
-  Scope(Vec<Node>),
-  VarDecl(u64, Expr),
-  VarSet(u64, Expr),
-  If(Expr, Box<Node>),
-  While(Expr, Box<Node>),
+  Scope(Vec<Spanned<Node>>),
+  VarDecl(u64, Spanned<Expr>),
+  VarSet(u64, Spanned<Expr>),
+  ArrayDecl(u64, MemoryUnit),
+  IndexSet(u64, Spanned<Expr>, Spanned<Expr>),
+  StructDecl(Struct),
+  StructVarDecl(u64, MemoryUnit),
+  FieldSet(u64, MemoryUnit, Spanned<Expr>),
+  DerefSet(Spanned<Expr>, Spanned<Expr>),
+  If(Spanned<Expr>, Box<Spanned<Node>>, Option<Box<Spanned<Node>>>),
+  While(Spanned<Expr>, Box<Spanned<Node>>),
   For(ForLoop),
-  Putchar(Expr),
+  Putchar(Spanned<Expr>),
+  Print(String),
   MethodDecl(Method),
-  Return(Expr),
+  Return(Spanned<Expr>),
+  ExprStmt(Spanned<Expr>),
   #[default]
   Invalid
 }
@@ -145,18 +184,27 @@ impl Display for Node {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
       Self::Scope(scope) => {
-        write!(f, "{{\n")?;
+        writeln!(f, "{{")?;
         scope.iter().for_each(|node| {let _ = write!(f, "\t{}", node);});
         write!(f, "}}")?;
       },
       Self::VarDecl(name, ex) => { write!(f, "let #{} = {}", name, ex)?; },
       Self::VarSet(name, ex) => { write!(f, "#{} = {}", name, ex)?; },
+      Self::ArrayDecl(name, len) => { write!(f, "let #{}[{}]", name, len)?; },
+      Self::IndexSet(name, index, ex) => { write!(f, "#{}[{}] = {}", name, index, ex)?; },
+      Self::StructDecl(strukt) => { write!(f, "struct {}[{}] {{ {} }}", strukt.name, strukt.id, strukt.fields.join(", "))?; },
+      Self::StructVarDecl(name, size) => { write!(f, "let #{}: {} cells", name, size)?; },
+      Self::FieldSet(name, offset, ex) => { write!(f, "#{}.{} = {}", name, offset, ex)?; },
+      Self::DerefSet(ptr, ex) => { write!(f, "*{} = {}", ptr, ex)?; },
       Self::Putchar(ex) => { write!(f, "putchar({})", ex)?; },
+      Self::Print(s) => { write!(f, "print({:?})", s)?; },
       Self::While(ex, body) => { write!(f, "while ({}) {}", ex, body)?; },
-      Self::If(ex, body) => { write!(f, "if ({}) {}", ex, body)?; },
-      Self::For(forloop) => { write!(f, "for ({} = {}; {}; {}) {}", forloop.var_name, forloop.start, forloop.condition, forloop.increment, forloop.body)?; },
+      Self::If(ex, body, None) => { write!(f, "if ({}) {}", ex, body)?; },
+      Self::If(ex, body, Some(otherwise)) => { write!(f, "if ({}) {} else {}", ex, body, otherwise)?; },
+      Self::For(forloop) => { write!(f, "for ({}#{} = {}; {}; {}) {}", forloop.var.name, forloop.var.id, forloop.start, forloop.condition, forloop.increment, forloop.body)?; },
       Self::MethodDecl(method) => { write!(f, "method {}[{}]({}) {}", method.name, method.id, method.parameters.iter().map(|v| format!("{}#{}", v.name, v.id)).collect::<Vec<String>>().join(", "), method.body)?; },
       Self::Return(e) => { write!(f, "return {}", e)?; },
+      Self::ExprStmt(e) => { write!(f, "{}", e)?; },
       Self::Invalid => { write!(f, "Invalid")?; }
     };
     Ok(())
@@ -172,21 +220,109 @@ pub struct Method {
   pub name: String,
   pub id: u64,
   pub parameters: Vec<Variable>,
-  pub body: Box<Node>
+  pub body: Box<Spanned<Node>>
 }
 
+
 pub struct Parser {
   base: Processor<Token>,
   vars: Vec<Variable>,
   methods: Vec<Method>,
+  structs: Vec<Struct>,
+  names: HashMap<u64, String>,
+  errors: Vec<DSAsmError>,
+  max_errors: usize,
+  truncated: bool,
+  in_method: bool,
+  machine: Machine,
 }
 
 impl Parser {
-  pub fn new(input: Vec<Token>) -> Parser {
-    Parser { base: Processor::new(input), vars: Vec::new(), methods: Vec::new() }
+  pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+  pub fn new(input: Vec<Spanned<Token>>) -> Parser {
+    Parser { base: Processor::spanned(input), vars: Vec::new(), methods: Vec::new(), structs: Vec::new(), names: HashMap::new(), errors: Vec::new(), max_errors: Parser::DEFAULT_MAX_ERRORS, truncated: false, in_method: false, machine: Machine::default() }
+  }
+
+  pub fn with_max_errors(mut self, max_errors: usize) -> Parser {
+    self.max_errors = max_errors.max(1);
+    self
+  }
+
+  pub fn with_machine(mut self, machine: Machine) -> Parser {
+    self.machine = machine;
+    self
+  }
+
+  pub fn symbols(&self) -> &HashMap<u64, String> {
+    &self.names
+  }
+
+  fn declare(&mut self, var: Variable) {
+    self.names.insert(var.id, var.name.clone());
+    self.vars.push(var);
+  }
+
+  fn record(&mut self, e: DSAsmError) {
+    if self.errors.len() < self.max_errors {
+      self.errors.push(e);
+    } else {
+      self.truncated = true;
+    }
+  }
+
+  fn synchronize(&mut self) {
+    while self.base.has_peek() {
+      match self.base.peek() {
+        Token::Semicolon => {
+          self.base.consume();
+          return;
+        },
+        Token::CloseCurly | Token::Method | Token::Let | Token::Struct => return,
+        _ => { self.base.consume(); }
+      }
+    }
+  }
+
+  fn recover(&mut self, e: DSAsmError, start: usize) {
+    self.record(e);
+    self.synchronize();
+    if self.errors.len() >= self.max_errors {
+      self.truncated |= self.base.has_peek();
+      self.base.exhaust();
+      return;
+    }
+    if self.base.get_peek() == start {
+      self.base.consume();
+    }
+  }
+
+  fn scoped<T, F>(&mut self, f: F) -> Result<T, DSAsmError> where F: FnOnce(&mut Self) -> Result<T, DSAsmError> {
+    let old = self.vars.clone();
+    let ret = f(self);
+    self.vars = old;
+    ret
+  }
+
+  fn require(&mut self, cmp: Token) -> Result<Token, DSAsmError> {
+    Ok(self.base.require(cmp).map_err(ParserError::Expected)?)
+  }
+
+  fn identifier(&mut self) -> Result<String, DSAsmError> {
+    match self.base.peek() {
+      Token::Identifier(s) => {
+        self.base.consume();
+        Ok(s)
+      },
+      _ => Err(ParserError::Expected(self.base.expected("identifier")).into())
+    }
+  }
+
+  fn spanned_from<T>(&self, start: &Span, value: T) -> Spanned<T> {
+    Spanned::new(value, start.to(&self.base.prev_span()))
   }
 
-  fn parseOperator(&mut self) -> Option<BinaryOperator> {
+  fn parse_operator(&mut self) -> Option<BinaryOperator> {
     let old = self.base.get_peek();
     let tmp = match self.base.consume() {
       Token::Plus => BinaryOperator::Add,
@@ -214,65 +350,177 @@ impl Parser {
     Some(tmp)
   }
 
-  fn parseExpr(&mut self, paren: bool) -> Result<Expr, DSAsmError> {
-    let mut left: Expr = match self.base.consume() {
+  fn parse_expr(&mut self) -> Result<Spanned<Expr>, DSAsmError> {
+    self.parse_binary(0)
+  }
+
+  fn parse_binary(&mut self, min_precedence: u64) -> Result<Spanned<Expr>, DSAsmError> {
+    let start = self.base.span();
+    let mut left = self.parse_unary()?;
+    loop {
+      let old = self.base.get_peek();
+      let operator = match self.parse_operator() {
+        Some(operator) if operator.precedence() >= min_precedence => operator,
+        _ => {
+          self.base.set_peek(old);
+          break;
+        }
+      };
+      let right = self.parse_binary(operator.precedence() + 1)?;
+      left = self.spanned_from(&start, Expr::Binary(Binary { left: Box::new(left), right: Box::new(right), operator }));
+    }
+    Ok(left)
+  }
+
+  fn parse_unary(&mut self) -> Result<Spanned<Expr>, DSAsmError> {
+    let start = self.base.span();
+    let old = self.base.get_peek();
+    let expr: Expr = match self.base.consume() {
       Token::Literal(val) => Expr::Literal(val),
       Token::Identifier(ident) => {
         if self.base.tryconsume(Token::OpenParen) {
-          let temp = self.methods.iter().find(|mtd| mtd.name == ident);
-          if temp.is_none() {
-            return Err(DSAsmError::ParserError(format!("Method '{}' does not exist", ident)).into())
-          }
-          let id = temp.map(|e| e.id).unwrap();
-          let mut params: Vec<Expr> = Vec::new();
+          let method = self.methods.iter().find(|mtd| mtd.name == ident).map(|e| (e.id, e.parameters.len()));
+          let mut params: Vec<Spanned<Expr>> = Vec::new();
           self.require_until(Token::CloseParen, |this| {
-            if params.len() > 0 {
-              this.base.require(Token::Comma).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
+            if !params.is_empty() {
+              this.require(Token::Comma)?;
             }
-            params.push(this.parseExpr(false)?);
+            params.push(this.parse_expr()?);
             Ok(())
           })?;
-          Expr::MethodCall(id, params)
+          match method {
+            Some((id, arity)) => {
+              if arity != params.len() {
+                self.record(ParserError::ArityMismatch { name: ident, expected: arity, found: params.len(), span: start.to(&self.base.prev_span()) }.into());
+              }
+              Expr::MethodCall(id, params)
+            },
+            None => {
+              self.record(ParserError::UndefinedMethod { name: ident, span: start.clone() }.into());
+              Expr::Literal(0)
+            }
+          }
+        } else if self.base.peek_equal(Token::OpenSquare) {
+          let (id, index) = self.parse_index(ident, &start)?;
+          Expr::Index(id, Box::new(index))
+        } else if self.base.peek_equal(Token::Dot) || self.arrow_follows() {
+          self.parse_field(ident, &start)?
         } else if let Some(ah) = self.vars.iter().find(|var| var.name == ident) {
           Expr::Variable(ah.id)
         } else {
-          return Err(DSAsmError::ParserError(format!("Variable '{}' does not exist", ident)).into())
+          self.record(ParserError::UndefinedVariable { name: ident, span: start.clone() }.into());
+          Expr::Literal(0)
         }
       },
       Token::Getchar => Expr::UserInput,
-      Token::Ampersand => Expr::Reference(Box::new(self.parseExpr(paren)?)),
-      Token::Star => Expr::Dereference(Box::new(self.parseExpr(paren)?)),
-      Token::Minus => Expr::Unary(Unary { right: Box::new(self.parseExpr(paren)?), operator: UnaryOperator::Negate }),
-      Token::Exclamation => Expr::Unary(Unary { right: Box::new(self.parseExpr(paren)?), operator: UnaryOperator::Not }),
-      Token::Tilde => Expr::Unary(Unary { right: Box::new(self.parseExpr(paren)?), operator: UnaryOperator::Bnot }),
-      Token::OpenParen => self.parseExpr(true)?,
-      t => {
-        return Err(DSAsmError::ParserError(format!("Invalid Token '{}'", t)).into())
+      Token::Str(s) => Expr::Str(s),
+      Token::Ampersand => Expr::Reference(Box::new(self.parse_unary()?)),
+      Token::Star => Expr::Dereference(Box::new(self.parse_unary()?)),
+      Token::Minus => {
+        let right = self.parse_unary()?;
+        match right.value {
+          Expr::Literal(lit) => Expr::Literal(lit.wrapping_neg() & self.machine.max()),
+          _ => Expr::Unary(Unary { right: Box::new(right), operator: UnaryOperator::Negate })
+        }
+      },
+      Token::Exclamation => Expr::Unary(Unary { right: Box::new(self.parse_unary()?), operator: UnaryOperator::Not }),
+      Token::Tilde => Expr::Unary(Unary { right: Box::new(self.parse_unary()?), operator: UnaryOperator::Bnot }),
+      Token::OpenParen => {
+        let inner = self.parse_expr()?;
+        self.require(Token::CloseParen)?;
+        inner.value
+      },
+      _ => {
+        self.base.set_peek(old);
+        return Err(ParserError::Expected(self.base.expected("expression")).into())
       }
     };
-    let operator = self.parseOperator();
-
-    if let Some(operator) = operator {
-      let right = self.parseExpr(false)?;
-      let new_expr = match right {
-        Expr::Binary(bin) => {
-          if operator.precedence() > bin.operator.precedence() {
-            let tmp = Expr::Binary(Binary { left: Box::new(left), right: bin.left, operator });
-            Expr::Binary(Binary { left: Box::new(tmp), right: bin.right, operator: bin.operator })
-          } else {
-            Expr::Binary(Binary { left: Box::new(left), right: Box::new(Expr::Binary(bin)), operator })
-          }
-        },
-        _ => {
-          Expr::Binary(Binary { left: Box::new(left), right: Box::new(right), operator })
+    Ok(self.spanned_from(&start, expr))
+  }
+
+  fn parse_index(&mut self, name: String, start: &Span) -> Result<(u64, Spanned<Expr>), DSAsmError> {
+    self.require(Token::OpenSquare)?;
+    let index = self.parse_expr()?;
+    self.require(Token::CloseSquare)?;
+    let span = start.to(&self.base.prev_span());
+    let id = match self.vars.iter().find(|var| var.name == name).cloned() {
+      Some(Variable { id, kind: VarKind::Array(len), .. }) => {
+        match index.value {
+          Expr::Literal(lit) if lit >= len => self.record(ParserError::IndexOutOfBounds { name, index: lit, len, span }.into()),
+          _ => {}
         }
-      };
-      left = new_expr;
+        id
+      },
+      Some(Variable { id, .. }) => {
+        self.record(ParserError::NotAnArray { name, span }.into());
+        id
+      },
+      None => {
+        self.record(ParserError::UndefinedVariable { name, span: start.clone() }.into());
+        0
+      }
     };
-    if paren {
-      self.base.require(Token::CloseParen).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
+    Ok((id, index))
+  }
+
+  fn arrow_follows(&mut self) -> bool {
+    let old = self.base.get_peek();
+    let arrow = self.base.tryconsume(Token::Minus) && self.base.peek_equal(Token::RightAngle);
+    self.base.set_peek(old);
+    arrow
+  }
+
+  fn parse_field(&mut self, name: String, start: &Span) -> Result<Expr, DSAsmError> {
+    let arrow = !self.base.tryconsume(Token::Dot);
+    if arrow {
+      self.require(Token::Minus)?;
+      self.require(Token::RightAngle)?;
+    }
+    let field_span = self.base.span();
+    let field = self.identifier()?;
+    let span = start.to(&self.base.prev_span());
+    let Some(var) = self.vars.iter().find(|var| var.name == name).cloned() else {
+      self.record(ParserError::UndefinedVariable { name, span: start.clone() }.into());
+      return Ok(Expr::Literal(0));
+    };
+    let id = match (var.kind, arrow) {
+      (VarKind::Struct(id), false) | (VarKind::Pointer(id), true) => id,
+      (_, false) => {
+        self.record(ParserError::NotAStruct { name, span }.into());
+        return Ok(Expr::Literal(0));
+      },
+      (_, true) => {
+        self.record(ParserError::NotAStructPointer { name, span }.into());
+        return Ok(Expr::Literal(0));
+      }
+    };
+    let strukt = self.structs.iter().find(|strukt| strukt.id == id).cloned().unwrap_or(Struct { name: String::new(), id, fields: Vec::new() });
+    let Some(offset) = strukt.offset(&field) else {
+      self.record(ParserError::UndefinedField { strukt: strukt.name, field, span: field_span }.into());
+      return Ok(Expr::Literal(0));
+    };
+    if arrow {
+      let ptr = Expr::Binary(Binary {
+        left: Box::new(Spanned::new(Expr::Variable(var.id), start.clone())),
+        right: Box::new(Spanned::new(Expr::Literal(offset), field_span)),
+        operator: BinaryOperator::Add
+      });
+      Ok(Expr::Dereference(Box::new(Spanned::new(ptr, span))))
+    } else {
+      Ok(Expr::Field(var.id, offset))
+    }
+  }
+
+  fn parse_struct_name(&mut self) -> Result<u64, DSAsmError> {
+    let span = self.base.span();
+    let name = self.identifier()?;
+    match self.structs.iter().find(|strukt| strukt.name == name) {
+      Some(strukt) => Ok(strukt.id),
+      None => {
+        self.record(ParserError::UndefinedStruct { name, span }.into());
+        Ok(0)
+      }
     }
-    return Ok(left);
   }
 
   fn do_until<F>(&mut self, cmp: Token, mut f: F) -> Result<bool, DSAsmError> where F: FnMut(&mut Self) -> Result<(), DSAsmError> {
@@ -287,117 +535,341 @@ impl Parser {
 
   fn require_until<F>(&mut self, cmp: Token, f: F) -> Result<(), DSAsmError> where F: FnMut(&mut Self) -> Result<(), DSAsmError> {
     if !self.do_until(cmp.clone(), f)? {
-      return Err(DSAsmError::ParserError(format!("Expected '{}' instead", cmp)).into())
+      return Err(ParserError::Expected(self.base.expected(format!("'{}'", cmp))).into())
     }
     Ok(())
   }
 
-  fn parse(&mut self) -> Result<Node, DSAsmError> {
+  fn parse(&mut self) -> Result<Spanned<Node>, DSAsmError> {
+    let start = self.base.span();
+    let old = self.base.get_peek();
     let node = match self.base.consume() {
       Token::OpenCurly => {
-        let mut scope: Vec<Node> = Vec::new();
-        let old = self.vars.clone();
-        self.require_until(Token::CloseCurly, |proc| {
-          scope.push(proc.parse()?);
+        let mut scope: Vec<Spanned<Node>> = Vec::new();
+        self.scoped(|this| this.require_until(Token::CloseCurly, |proc| {
+          let old = proc.base.get_peek();
+          match proc.parse() {
+            Ok(node) => scope.push(node),
+            Err(e) => proc.recover(e, old)
+          }
           Ok(())
-        })?;
-        self.vars = old;
+        }))?;
         Node::Scope(scope)
       },
       Token::Let => {
-        let name = match self.base.consume() {
-          Token::Identifier(s) => s,
-          t => {
-            return Err(DSAsmError::ParserError(format!("Unexpected '{}', expected identifier instead", t)).into());
-          }
-        };
-        if self.vars.iter().find(|e| e.name == name).is_some() {
-          return Err(DSAsmError::ParserError(format!("Variable '{}' already exists", name)).into());
+        let name_span = self.base.span();
+        let name = self.identifier()?;
+        if self.vars.iter().any(|e| e.name == name) {
+          self.record(ParserError::DuplicateVariable { name: name.clone(), span: name_span }.into());
         };
-        let var: Variable = Variable { name: name.clone(), id: generate_id() };
-        self.vars.push(var.clone());
-        self.base.require(Token::Equals).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
-        Node::VarDecl(var.id, self.parseExpr(false)?)
+        if self.base.tryconsume(Token::OpenSquare) {
+          let len_span = self.base.span();
+          let len = match self.base.peek() {
+            Token::Literal(len) => {
+              self.base.consume();
+              len
+            },
+            _ => return Err(ParserError::Expected(self.base.expected("array length")).into())
+          };
+          self.require(Token::CloseSquare)?;
+          if len == 0 {
+            self.record(ParserError::EmptyArray { name: name.clone(), span: len_span }.into());
+          }
+          let var: Variable = Variable { name, id: generate_id(), kind: VarKind::Array(len.max(1)) };
+          self.declare(var.clone());
+          Node::ArrayDecl(var.id, len.max(1))
+        } else if self.base.tryconsume(Token::LabelDef) {
+          let pointer = self.base.tryconsume(Token::Star);
+          let strukt = self.parse_struct_name()?;
+          let kind = if pointer { VarKind::Pointer(strukt) } else { VarKind::Struct(strukt) };
+          let var: Variable = Variable { name, id: generate_id(), kind };
+          self.declare(var.clone());
+          if pointer {
+            self.require(Token::Equals)?;
+            Node::VarDecl(var.id, self.parse_expr()?)
+          } else {
+            let size = self.structs.iter().find(|s| s.id == strukt).map(Struct::size).unwrap_or(1);
+            Node::StructVarDecl(var.id, size)
+          }
+        } else {
+          let var: Variable = Variable { name, id: generate_id(), kind: VarKind::Scalar };
+          self.declare(var.clone());
+          self.require(Token::Equals)?;
+          Node::VarDecl(var.id, self.parse_expr()?)
+        }
+      },
+      Token::Identifier(_) | Token::Getchar if !self.base.peek_equal(Token::Equals) => {
+        self.base.set_peek(old);
+        let errors = self.errors.len();
+        let target = self.parse_expr()?;
+        if self.base.tryconsume(Token::Equals) {
+          let value = self.parse_expr()?;
+          match target.value {
+            Expr::Index(id, index) => Node::IndexSet(id, *index, value),
+            Expr::Field(id, offset) => Node::FieldSet(id, offset, value),
+            Expr::Dereference(ptr) => Node::DerefSet(*ptr, value),
+            _ => {
+              if self.errors.len() == errors {
+                self.record(ParserError::InvalidAssignment { span: target.span.clone() }.into());
+              }
+              Node::ExprStmt(target)
+            }
+          }
+        } else {
+          Node::ExprStmt(target)
+        }
+      },
+      Token::Struct => {
+        let name_span = self.base.span();
+        let name = self.identifier()?;
+        if self.structs.iter().any(|strukt| strukt.name == name) {
+          self.record(ParserError::DuplicateStruct { name: name.clone(), span: name_span }.into());
+        }
+        self.require(Token::OpenCurly)?;
+        let mut fields: Vec<String> = Vec::new();
+        self.require_until(Token::CloseCurly, |this| {
+          if !fields.is_empty() {
+            this.require(Token::Comma)?;
+          }
+          let span = this.base.span();
+          let field = this.identifier()?;
+          if fields.contains(&field) {
+            this.record(ParserError::DuplicateField { strukt: name.clone(), field: field.clone(), span }.into());
+          }
+          fields.push(field);
+          Ok(())
+        })?;
+        let strukt = Struct { name, id: generate_id(), fields };
+        self.structs.push(strukt.clone());
+        Node::StructDecl(strukt)
       },
       Token::Identifier(name) => {
-        let var = self.vars.iter().find(|e| e.name == name);
-        if var.is_none() {
-          return Err(DSAsmError::ParserError(format!("Variable '{}' does not exists", name)).into());
+        let id = match self.vars.iter().find(|e| e.name == name) {
+          Some(var) => var.id,
+          None => {
+            self.record(ParserError::UndefinedVariable { name, span: start.clone() }.into());
+            0
+          }
         };
-        self.base.require(Token::Equals).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
-        Node::VarSet(var.unwrap().id, self.parseExpr(false)?)
+        self.require(Token::Equals)?;
+        Node::VarSet(id, self.parse_expr()?)
+      },
+      Token::Star => {
+        let ptr = self.parse_unary()?;
+        self.require(Token::Equals)?;
+        Node::DerefSet(ptr, self.parse_expr()?)
       },
       Token::For => {
-        self.base.require(Token::OpenParen).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
-        let name = match self.base.consume() {
-          Token::Identifier(s) => s,
-          t => {
-            return Err(DSAsmError::ParserError(format!("Unexpected '{}', expected identifier instead", t)).into());
-          }
-        };
-        self.base.require(Token::Equals).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
-        let old = self.vars.clone();
-        self.vars.push(Variable { name: name.clone(), id: generate_id() });
-        let start = self.parseExpr(false)?;
-        self.base.require(Token::Semicolon).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
-        let cond = self.parseExpr(false)?;
-        self.base.require(Token::Semicolon).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
-        let inc = self.parse()?;
-        self.base.require(Token::CloseParen).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
+        self.require(Token::OpenParen)?;
+        let name = self.identifier()?;
+        self.require(Token::Equals)?;
+        self.scoped(|this| {
+          let var = Variable { name, id: generate_id(), kind: VarKind::Scalar };
+          this.declare(var.clone());
+          let start = this.parse_expr()?;
+          this.require(Token::Semicolon)?;
+          let cond = this.parse_expr()?;
+          this.require(Token::Semicolon)?;
+          let inc = this.parse()?;
+          this.require(Token::CloseParen)?;
+          let body = this.parse()?;
+          Ok(Node::For(ForLoop { var, start, condition: cond, increment: Box::new(inc), body: Box::new(body) }))
+        })?
+      },
+      Token::If => {
+        let cond = self.parse_expr()?;
         let body = self.parse()?;
-        self.vars = old;
-        Node::For(ForLoop { var_name: name, start, condition: cond, increment: Box::new(inc), body: Box::new(body) })
+        let otherwise = if self.base.tryconsume(Token::Else) {
+          Some(Box::new(self.parse()?))
+        } else {
+          None
+        };
+        Node::If(cond, Box::new(body), otherwise)
+      },
+      Token::While => Node::While(self.parse_expr()?, Box::new(self.parse()?)),
+      Token::Putchar => Node::Putchar(self.parse_expr()?),
+      Token::Print => {
+        self.require(Token::OpenParen)?;
+        let s = match self.base.peek() {
+          Token::Str(s) => {
+            self.base.consume();
+            s
+          },
+          _ => return Err(ParserError::Expected(self.base.expected("string literal")).into())
+        };
+        self.require(Token::CloseParen)?;
+        Node::Print(s)
+      },
+      Token::Return => {
+        if !self.in_method {
+          self.record(ParserError::ReturnOutsideMethod { span: start.clone() }.into());
+        }
+        Node::Return(self.parse_expr()?)
       },
-      Token::If => Node::If(self.parseExpr(false)?, Box::new(self.parse()?)),
-      Token::While => Node::While(self.parseExpr(false)?, Box::new(self.parse()?)),
-      Token::Putchar => Node::Putchar(self.parseExpr(false)?),
-      Token::Return => Node::Return(self.parseExpr(false)?),
 
-      t => {
-        return Err(DSAsmError::ParserError(format!("Unexpected '{}'", t)).into());
+      _ => {
+        self.base.set_peek(old);
+        return Err(ParserError::Expected(self.base.expected("statement")).into());
       }
     };
+    self.base.tryconsume(Token::Semicolon);
     
-    Ok(node)
+    Ok(self.spanned_from(&start, node))
   }
-  pub fn parse_all(&mut self) -> Result<Vec<Node>, DSAsmError> {
-    let mut nodes: Vec<Node> = Vec::new();
-    while self.base.has_peek() {
-      if self.base.tryconsume(Token::Method) {
-        let name = match self.base.consume() {
-          Token::Identifier(s) => s,
-          t => {
-            return Err(DSAsmError::ParserError(format!("Unexpected '{}', expected identifier instead", t)).into());
-          }
+
+  fn parse_method(&mut self) -> Result<Spanned<Node>, DSAsmError> {
+    let start = self.base.span();
+    self.require(Token::Method)?;
+    let name_span = self.base.span();
+    let name = self.identifier()?;
+    if self.methods.iter().any(|mtd| mtd.name == name) {
+      self.record(ParserError::DuplicateMethod { name: name.clone(), span: name_span }.into());
+    }
+    self.require(Token::OpenParen)?;
+    let mut params: Vec<Variable> = Vec::new();
+    let id = generate_id();
+    let node = self.scoped(|this| {
+      this.require_until(Token::CloseParen, |this| {
+        if !params.is_empty() {
+          this.require(Token::Comma)?;
+        }
+        let s = this.identifier()?;
+        let kind = if this.base.tryconsume(Token::LabelDef) {
+          this.require(Token::Star)?;
+          VarKind::Pointer(this.parse_struct_name()?)
+        } else {
+          VarKind::Scalar
         };
-        self.base.require(Token::OpenParen).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
-        let mut params: Vec<Variable> = Vec::new();
-        let old = self.vars.clone();
-        self.require_until(Token::CloseParen, |this| {
-          if params.len() > 0 {
-            this.base.require(Token::Comma).map_err(|e| Err::<(), DSAsmError>(DSAsmError::ParserError(format!("{}", e)).into()))?;
-          }
-          match this.base.consume() {
-            Token::Identifier(s) => {
-              let var: Variable = Variable {id: generate_id(), name: s.clone()};
-              this.vars.push(var.clone());
-              params.push(var);
-              return Ok(())
-            },
-            t => {
-              return Err(DSAsmError::ParserError(format!("Unexpected '{}', expected identifier instead", t)).into());
-            }
-          };
-        })?;
-        let node = self.parse()?;
-        self.vars = old;
-        let mtd: Method = Method { name, id: generate_id(), parameters: params, body: Box::new(node) };
-        self.methods.push(mtd.clone());
-        nodes.push(Node::MethodDecl(mtd));
+        let var: Variable = Variable {id: generate_id(), name: s, kind};
+        this.declare(var.clone());
+        params.push(var);
+        Ok(())
+      })?;
+      this.methods.push(Method { name: name.clone(), id, parameters: params.clone(), body: Box::default() });
+      this.in_method = true;
+      let body = this.parse();
+      this.in_method = false;
+      body
+    })?;
+    let mtd: Method = Method { name, id, parameters: params, body: Box::new(node) };
+    if let Some(declared) = self.methods.iter_mut().find(|mtd| mtd.id == id) {
+      *declared = mtd.clone();
+    }
+    Ok(self.spanned_from(&start, Node::MethodDecl(mtd)))
+  }
+
+  pub fn parse_all(&mut self) -> Result<Vec<Spanned<Node>>, DSAsmError> {
+    let mut nodes: Vec<Spanned<Node>> = Vec::new();
+    while self.base.has_peek() {
+      let old = self.base.get_peek();
+      let node = if self.base.peek_equal(Token::Method) {
+        self.parse_method()
       } else {
-        nodes.push(self.parse()?);
+        self.parse()
+      };
+      match node {
+        Ok(node) => nodes.push(node),
+        Err(e) => self.recover(e, old)
       }
     }
-    Ok(nodes)
+    match self.errors.len() {
+      0 => Ok(nodes),
+      1 if !self.truncated => Err(self.errors.remove(0)),
+      _ => Err(DSAsmError::Multiple { errors: std::mem::take(&mut self.errors), truncated: self.truncated })
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::tokenizer::Tokenizer;
+
+  fn parse(source: &str) -> Result<Vec<Spanned<Node>>, DSAsmError> {
+    let tokens = Tokenizer::new(source.chars().collect(), "test").tokenize()?;
+    Parser::new(tokens).parse_all()
+  }
+
+  fn shape(expr: &str) -> String {
+    let tokens = Tokenizer::new(format!("let a = 0; let b = 0; let c = 0; let d = 0; let r = {};", expr).chars().collect(), "test").tokenize().unwrap();
+    let mut parser = Parser::new(tokens);
+    let nodes = parser.parse_all().unwrap();
+    let Some(Node::VarDecl(_, value)) = nodes.last().map(|node| &node.value) else {
+      panic!("expected a declaration");
+    };
+    parser.symbols().iter().fold(value.to_string(), |shape, (id, name)| shape.replace(&format!("${{#{}}}", id), name))
+  }
+
+  #[test]
+  fn subtraction_is_left_associative() {
+    assert_eq!(shape("a - b - c"), "((a - b) - c)");
+  }
+
+  #[test]
+  fn products_bind_tighter_than_sums() {
+    assert_eq!(shape("a * b + c * d"), "((a * b) + (c * d))");
+  }
+
+  #[test]
+  fn parenthesized_sub_expressions_followed_by_operators() {
+    assert_eq!(shape("(a - b) * c"), "((a - b) * c)");
+    assert_eq!(shape("a * (b + c) - d"), "((a * (b + c)) - d)");
+    assert_eq!(shape("(a) + (b) * c"), "(a + (b * c))");
+    assert_eq!(shape("((a + b)) - (c - d) - a"), "(((a + b) - (c - d)) - a)");
+  }
+
+  #[test]
+  fn every_precedence_pair() {
+    use BinaryOperator::*;
+    let levels: [&[BinaryOperator]; 9] = [
+      &[Or], &[And], &[Bor], &[Band], &[Equals, NotEquals], &[Greater, Less, Grequ, Lessequ], &[ShiftL, ShiftR], &[Add, Sub], &[Mult, Div, Modulus]
+    ];
+    for (i, first) in levels.iter().enumerate() {
+      for (j, second) in levels.iter().enumerate() {
+        for x in first.iter() {
+          for y in second.iter() {
+            let expected = if i >= j { format!("((1 {} 2) {} 3)", x, y) } else { format!("(1 {} (2 {} 3))", x, y) };
+            assert_eq!(shape(&format!("1 {} 2 {} 3", x, y)), expected, "{} followed by {}", x, y);
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn syntax_errors_past_the_cap_are_reported_as_truncated() {
+    let source = "let = 1;\n".repeat(Parser::DEFAULT_MAX_ERRORS + 5);
+    match parse(&source) {
+      Err(DSAsmError::Multiple { errors, truncated }) => {
+        assert_eq!(errors.len(), Parser::DEFAULT_MAX_ERRORS);
+        assert!(truncated);
+      },
+      other => panic!("expected multiple errors, got {:?}", other)
+    }
+  }
+
+  #[test]
+  fn errors_up_to_the_cap_are_not_truncated() {
+    let source = "let = 1;\n".repeat(Parser::DEFAULT_MAX_ERRORS);
+    match parse(&source) {
+      Err(DSAsmError::Multiple { errors, truncated }) => {
+        assert_eq!(errors.len(), Parser::DEFAULT_MAX_ERRORS);
+        assert!(!truncated);
+      },
+      other => panic!("expected multiple errors, got {:?}", other)
+    }
+  }
+
+  #[test]
+  fn negative_literals_fold_to_the_word_size() {
+    for (bits, expected) in [(8, 0xFF), (16, 0xFFFF), (32, 0xFFFF_FFFF)] {
+      let machine = Machine::new(bits, 16);
+      let tokens = Tokenizer::new("let x = -1;".chars().collect(), "test").with_machine(machine).tokenize().unwrap();
+      let nodes = Parser::new(tokens).with_machine(machine).parse_all().unwrap();
+      let Node::VarDecl(_, value) = &nodes[0].value else {
+        panic!("expected a declaration");
+      };
+      assert_eq!(value.value, Expr::Literal(expected));
+    }
   }
 }
@@ -1,17 +1,22 @@
-use std::{error::Error, fmt::{Debug, Display}};
+use std::fmt::{Debug, Display};
 
-use crate::core::error::DSAsmError;
+use crate::core::{error::ExpectedError, span::{Span, Spanned}};
 
 pub trait ProcessorInput: Display + Debug + Default + Clone + Eq + PartialEq { }
 
 pub struct Processor<I: ProcessorInput> {
   input: Vec<I>,
+  spans: Vec<Span>,
   peek: usize
 }
 
 impl<I: ProcessorInput> Processor<I>  {
   pub fn new(i: Vec<I>) -> Processor<I> {
-    Processor { input: i, peek: 0 }
+    Processor { input: i, spans: Vec::new(), peek: 0 }
+  }
+  pub fn spanned(i: Vec<Spanned<I>>) -> Processor<I> {
+    let (input, spans) = i.into_iter().map(|s| (s.value, s.span)).unzip();
+    Processor { input, spans, peek: 0 }
   }
   pub fn has_peek(&self) -> bool {
     self.peek < self.input.len()
@@ -43,18 +48,37 @@ impl<I: ProcessorInput> Processor<I>  {
       false
     }
   }
-  pub fn require(&mut self, cmp: I) -> Result<I, Box<dyn Error>> {
+  pub fn require(&mut self, cmp: I) -> Result<I, ExpectedError> {
     if self.peek_equal(cmp.clone()) {
       Ok(self.consume())
     } else {
-      Err(DSAsmError::ProcessorError(format!("Expected '{}', peek={} - ", cmp, self.peek)).into())
+      Err(self.expected(format!("'{}'", cmp)))
     }
   }
+  pub fn expected(&self, expected: impl Into<String>) -> ExpectedError {
+    let found = if self.has_peek() { format!("'{}'", self.peek()) } else { "end of file".into() };
+    ExpectedError { expected: expected.into(), found, span: self.span() }
+  }
+  pub fn get(&self, index: usize) -> Option<&I> {
+    self.input.get(index)
+  }
   pub fn get_peek(&self) -> usize {
     self.peek
   }
   pub fn set_peek(&mut self, u: usize) {
     self.peek = u;
   }
+  pub fn exhaust(&mut self) {
+    self.peek = self.input.len();
+  }
+  pub fn span_at(&self, index: usize) -> Span {
+    self.spans.get(index).or(self.spans.last()).cloned().unwrap_or_default()
+  }
+  pub fn span(&self) -> Span {
+    self.span_at(self.peek)
+  }
+  pub fn prev_span(&self) -> Span {
+    self.span_at(self.peek.saturating_sub(1))
+  }
   
 }
@@ -0,0 +1,51 @@
+use std::{fmt::Display, rc::Rc};
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Hash)]
+pub struct Span {
+  pub file: Rc<str>,
+  pub line: usize,
+  pub column: usize,
+  pub len: usize,
+}
+
+impl Span {
+  pub fn new(file: Rc<str>, line: usize, column: usize, len: usize) -> Span {
+    Span { file, line, column, len }
+  }
+
+  // Instructions loaded from a stripped image have no location to point at.
+  pub fn is_known(&self) -> bool {
+    !self.file.is_empty()
+  }
+
+  pub fn to(&self, end: &Span) -> Span {
+    if self.file != end.file || end.line != self.line || end.column < self.column {
+      return self.clone();
+    }
+    Span { file: self.file.clone(), line: self.line, column: self.column, len: end.column + end.len - self.column }
+  }
+}
+
+impl Display for Span {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}:{}:{}", self.file, self.line, self.column)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Spanned<T> {
+  pub value: T,
+  pub span: Span,
+}
+
+impl<T> Spanned<T> {
+  pub fn new(value: T, span: Span) -> Spanned<T> {
+    Spanned { value, span }
+  }
+}
+
+impl<T: Display> Display for Spanned<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.value)
+  }
+}
@@ -1,6 +1,6 @@
-use std::fmt::Display;
+use std::{fmt::Display, num::IntErrorKind, rc::Rc};
 
-use crate::core::{error::DSAsmError, interpreter::MemoryUnit, processor::{Processor, ProcessorInput}};
+use crate::core::{error::{DSAsmError, TokenizerError}, interpreter::{Machine, MemoryUnit}, processor::{Processor, ProcessorInput}, span::{Span, Spanned}};
 
 #[derive(PartialEq, Eq, Clone, Default, Debug)]
 pub enum Token {
@@ -25,6 +25,7 @@ pub enum Token {
   Reduce,
 
   Literal(MemoryUnit),
+  Str(String),
   Ampersand,
   Tilde,
   Percent,
@@ -37,12 +38,15 @@ pub enum Token {
   OpenCurly,
   CloseCurly,
   If,
+  Else,
   While,
   For,
   Putchar,
   Return,
   Method,
   Getchar,
+  Struct,
+  Print,
   Semicolon,
 
   #[default]
@@ -61,22 +65,73 @@ impl ProcessorInput for char { }
 
 pub struct Tokenizer {
   base: Processor<char>,
+  file: Rc<str>,
+  machine: Machine,
   line: usize,
+  line_start: usize,
   comment: bool,
 }
 
 impl Tokenizer {
-  pub fn new(content: Vec<char>) -> Tokenizer {
+  pub fn new(content: Vec<char>, file: &str) -> Tokenizer {
     Tokenizer { 
       base: Processor::new(content), 
-      line: 0,
+      file: file.into(),
+      machine: Machine::default(),
+      line: 1,
+      line_start: 0,
       comment: false
     }
   }
 
-  pub fn tokenize(&mut self) -> Result<Vec<Token>, DSAsmError> {
-    let mut ret: Vec<Token> = Vec::new();
+  pub fn with_machine(mut self, machine: Machine) -> Tokenizer {
+    self.machine = machine;
+    self
+  }
+
+  fn span_from(&self, start: usize) -> Span {
+    Span::new(self.file.clone(), self.line, start - self.line_start + 1, self.base.get_peek() - start)
+  }
+
+  fn unit(&self, ch: char, start: usize) -> Result<MemoryUnit, DSAsmError> {
+    match MemoryUnit::try_from(ch as u32) {
+      Ok(unit) if unit <= self.machine.max() => Ok(unit),
+      _ => Err(TokenizerError::CharOutOfRange { ch, span: self.span_from(start) }.into())
+    }
+  }
+
+  fn escape(&mut self, start: usize) -> Result<char, DSAsmError> {
+    let ch = match self.base.consume() {
+      'n' => '\n',
+      't' => '\t',
+      'r' => '\r',
+      '0' => '\0',
+      '\\' => '\\',
+      '\'' => '\'',
+      '"' => '"',
+      'x' => {
+        let mut digits = String::new();
+        while digits.len() < 2 && self.base.peek().is_ascii_hexdigit() {
+          digits.push(self.base.consume());
+        }
+        match u8::from_str_radix(&digits, 16) {
+          Ok(code) if digits.len() == 2 => code as char,
+          _ => return Err(TokenizerError::InvalidEscape { sequence: format!("\\x{}", digits), span: self.span_from(start) }.into())
+        }
+      },
+      ch => return Err(TokenizerError::InvalidEscape { sequence: format!("\\{}", ch), span: self.span_from(start) }.into())
+    };
+    Ok(ch)
+  }
+
+  pub fn tokenize(&mut self) -> Result<Vec<Spanned<Token>>, DSAsmError> {
+    let mut ret: Vec<Spanned<Token>> = Vec::new();
     while self.base.has_peek() {
+      let start = self.base.get_peek();
+      if self.comment && !self.base.peek_equal('\n') {
+        self.base.consume();
+        continue;
+      }
       let token: Token = match self.base.consume() {
         '>' => Token::RightAngle,
         '<' => Token::LeftAngle,
@@ -95,7 +150,36 @@ impl Tokenizer {
         '~' => Token::Tilde,
         '[' => Token::OpenSquare,
         ']' => Token::CloseSquare,
-        '\'' => Token::Apostrophe,
+        '\'' => {
+          let old = self.base.get_peek();
+          let ch = match self.base.consume() {
+            '\\' => Some(self.escape(start)?),
+            '\'' | '\n' => None,
+            ch => Some(ch)
+          };
+          match ch {
+            Some(ch) if self.base.tryconsume('\'') => Token::Literal(self.unit(ch, start)?),
+            _ => {
+              self.base.set_peek(old);
+              Token::Apostrophe
+            }
+          }
+        },
+        '"' => {
+          let mut buf = String::new();
+          loop {
+            match self.base.consume() {
+              '"' => break,
+              '\\' => buf.push(self.escape(start)?),
+              '\n' | '\0' => return Err(TokenizerError::UnterminatedString { span: self.span_from(start) }.into()),
+              ch => {
+                self.unit(ch, start)?;
+                buf.push(ch);
+              }
+            }
+          }
+          Token::Str(buf)
+        },
         '§' => Token::Or,
         '°' => Token::Reduce,
 
@@ -115,53 +199,161 @@ impl Tokenizer {
           }
           if ch == '\n' {
             self.line += 1;
+            self.line_start = self.base.get_peek();
             self.comment = false;
             continue;
           }
           if self.comment || ch.is_whitespace() {
             continue;
           }
-          if ch.is_alphabetic() {
+          if ch.is_ascii_alphabetic() || ch == '_' {
             let mut buf = String::from(ch);
-            while self.base.peek().is_alphabetic() {
+            while self.base.peek().is_ascii_alphanumeric() || self.base.peek() == '_' {
               buf.push(self.base.consume());
             };
 
             match buf.as_str() {
               "let" => Token::Let,
               "if" => Token::If,
+              "else" => Token::Else,
               "while" => Token::While,
               "for" => Token::For,
               "putchar" => Token::Putchar,
               "return" => Token::Return,
               "method" => Token::Method,
               "getchar" => Token::Getchar,
+              "struct" => Token::Struct,
+              "print" => Token::Print,
               buf => {
                 Token::Identifier(buf.to_string())
               }
             }
-          } else if ch.is_digit(10) {
-            let mut buf = String::from(ch);
-            while self.base.peek().is_digit(10) {
-              buf.push(self.base.consume());
+          } else if ch.is_ascii_digit() {
+            let radix = match self.base.peek() {
+              'x' if ch == '0' => 16,
+              'b' if ch == '0' => 2,
+              'o' if ch == '0' => 8,
+              _ => 10
             };
-            let ret = buf.parse::<MemoryUnit>().map_err(|e| Err::<MemoryUnit, DSAsmError>(DSAsmError::TokenizerError(format!("{}", e)).into()))?;
-            Token::Literal(ret)
-          } else if ch == '0' && self.base.tryconsume('x') && self.base.peek().is_digit(16) {
-            let mut buf = String::from(ch);
-            while self.base.peek().is_digit(16) {
-              buf.push(self.base.consume());
+            let mut literal = String::from(ch);
+            if radix != 10 {
+              literal.push(self.base.consume());
+            }
+            while self.base.peek().is_ascii_alphanumeric() || self.base.peek() == '_' {
+              literal.push(self.base.consume());
             };
-            let ret: MemoryUnit = MemoryUnit::from_str_radix(&buf, 16).map_err(|e| Err::<MemoryUnit, DSAsmError>(DSAsmError::TokenizerError(format!("{}", e)).into()))?;
+            let digits: String = literal.chars().skip(if radix == 10 { 0 } else { 2 }).filter(|c| *c != '_').collect();
+            let overflow = || TokenizerError::LiteralOverflow { literal: literal.clone(), bits: self.machine.bits, span: self.span_from(start) };
+            let ret = MemoryUnit::from_str_radix(&digits, radix).map_err(|e| match e.kind() {
+              IntErrorKind::PosOverflow => overflow(),
+              _ => TokenizerError::InvalidLiteral { literal: literal.clone(), span: self.span_from(start), source: e }
+            })?;
+            if ret > self.machine.max() {
+              return Err(overflow().into());
+            }
             Token::Literal(ret)
           } else {
-            return Err(DSAsmError::TokenizerError(format!("Invalid token {}[{}]", ch, self.line)).into())
+            return Err(TokenizerError::InvalidCharacter { ch, span: self.span_from(start) }.into())
           }
         }
       };
-      ret.push(token);
+      ret.push(Spanned::new(token, self.span_from(start)));
     }
 
     Ok(ret)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::core::{error::{DSAsmError, TokenizerError}, interpreter::Machine, tokenizer::{Token, Tokenizer}};
+
+  fn tokenize(source: &str, machine: Machine) -> Result<Vec<Token>, DSAsmError> {
+    Ok(Tokenizer::new(source.chars().collect(), "test").with_machine(machine).tokenize()?.into_iter().map(|token| token.value).collect())
+  }
+
+  fn tokens(source: &str) -> Vec<Token> {
+    tokenize(source, Machine::default()).unwrap()
+  }
+
+  fn error(source: &str) -> TokenizerError {
+    match tokenize(source, Machine::default()) {
+      Err(DSAsmError::Tokenizer(e)) => e,
+      result => panic!("expected a tokenizer error for {:?}, got {:?}", source, result)
+    }
+  }
+
+  #[test]
+  fn escapes_in_char_literals() {
+    let source = r#"'\n' '\t' '\0' '\\' '\'' '\"' '\x41' '\x7e'"#;
+    assert_eq!(tokens(source), [10, 9, 0, 92, 39, 34, 65, 126].map(Token::Literal));
+  }
+
+  #[test]
+  fn escapes_in_strings() {
+    assert_eq!(tokens(r#""a\n\t\0\\'\"\x42""#), [Token::Str("a\n\t\0\\'\"B".to_string())]);
+  }
+
+  #[test]
+  fn invalid_escapes_are_rejected() {
+    assert!(matches!(error(r"'\q'"), TokenizerError::InvalidEscape { sequence, .. } if sequence == r"\q"));
+    assert!(matches!(error(r#""\q""#), TokenizerError::InvalidEscape { sequence, .. } if sequence == r"\q"));
+  }
+
+  #[test]
+  fn hex_escapes_need_two_digits() {
+    assert!(matches!(error(r"'\x4'"), TokenizerError::InvalidEscape { sequence, .. } if sequence == r"\x4"));
+    assert!(matches!(error(r#""\xg1""#), TokenizerError::InvalidEscape { sequence, .. } if sequence == r"\x"));
+  }
+
+  #[test]
+  fn unterminated_strings_are_rejected() {
+    assert!(matches!(error("\"abc"), TokenizerError::UnterminatedString { .. }));
+    assert!(matches!(error("\"abc\nlet x;"), TokenizerError::UnterminatedString { .. }));
+  }
+
+  #[test]
+  fn chars_wider_than_a_cell_are_rejected() {
+    let machine = Machine::new(8, 16);
+    assert_eq!(tokenize("'\u{FF}'", machine).unwrap(), [Token::Literal(0xFF)]);
+    assert!(matches!(tokenize("'\u{100}'", machine), Err(DSAsmError::Tokenizer(TokenizerError::CharOutOfRange { ch: '\u{100}', .. }))));
+    assert!(matches!(tokenize("\"a\u{100}\"", machine), Err(DSAsmError::Tokenizer(TokenizerError::CharOutOfRange { ch: '\u{100}', .. }))));
+    assert_eq!(tokens("'\u{100}'"), [Token::Literal(0x100)]);
+  }
+
+  #[test]
+  fn lone_apostrophes_fall_back_to_compare() {
+    assert_eq!(tokens("'5"), [Token::Apostrophe, Token::Literal(5)]);
+    assert_eq!(tokens("'ab'"), [Token::Apostrophe, Token::Identifier("ab".to_string()), Token::Apostrophe]);
+    assert_eq!(tokens("''"), [Token::Apostrophe, Token::Apostrophe]);
+  }
+
+  #[test]
+  fn numeric_literals_accept_prefixes_and_separators() {
+    assert_eq!(tokens("0x1F 0b101 0o17 1_000 0xFF_FF 0b1111_0000 007"), [Token::Literal(31), Token::Literal(5), Token::Literal(15), Token::Literal(1000), Token::Literal(65535), Token::Literal(240), Token::Literal(7)]);
+  }
+
+  #[test]
+  fn numeric_literals_overflow_at_the_cell_width() {
+    assert_eq!(tokenize("255", Machine::new(8, 16)).unwrap(), [Token::Literal(255)]);
+    for (source, bits) in [("256", 8), ("0x1_00", 8), ("65536", 16), ("0o200000", 16), ("4294967296", 32)] {
+      let result = tokenize(source, Machine::new(bits, 16));
+      assert!(matches!(result, Err(DSAsmError::Tokenizer(TokenizerError::LiteralOverflow { ref literal, bits: b, .. })) if literal == source && b == bits), "{}: {:?}", source, result);
+    }
+  }
+
+  #[test]
+  fn malformed_numeric_literals_are_rejected() {
+    for source in ["0xZZ", "0x", "0XAB", "0b102", "0o8", "12ab"] {
+      assert!(matches!(error(source), TokenizerError::InvalidLiteral { literal, .. } if literal == source), "{}", source);
+    }
+  }
+
+  #[test]
+  fn identifiers_and_keywords() {
+    let ident = |name: &str| Token::Identifier(name.to_string());
+    assert_eq!(tokens("let letter _x1 print2 print _ a_b_9 X method methods"), [Token::Let, ident("letter"), ident("_x1"), ident("print2"), Token::Print, ident("_"), ident("a_b_9"), ident("X"), Token::Method, ident("methods")]);
+    assert_eq!(tokens("x9-y"), [ident("x9"), Token::Minus, ident("y")]);
+    assert!(matches!(error("9x"), TokenizerError::InvalidLiteral { .. }));
+  }
+}
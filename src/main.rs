@@ -1,26 +1,108 @@
-use std::{env::args, fs::{self, File}};
+use std::{collections::HashMap, env::args, fs, io, path::Path, process::ExitCode, time::Duration};
 
-use crate::core::{bytecode::BytecodeConverter, error::DSAsmError, generation::Generator, interpreter::{Interpreter}, parser::Parser, tokenizer::Tokenizer};
+use disassembly::core::{binary::Image, bytecode::{self, BytecodeConverter}, diagnostics::{self, Diagnostic}, disassembler::Disassembler, error::DSAsmError, generation::Generator, interpreter::{Arithmetic, EofPolicy, Interpreter, Machine}, parser::Parser, tokenizer::Tokenizer};
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Emit {
+  Asm,
+  Disasm,
+  Bytecode,
+}
 
-mod core;
-
-fn main() -> Result<(), DSAsmError>{
+struct Options {
+  raw: bool,
+  debug: bool,
+  bounds: bool,
+  strip: bool,
+  trap: bool,
+  eof: EofPolicy,
+  max_instructions: Option<u64>,
+  timeout: Option<Duration>,
+  machine: Machine,
+  bits: Option<u32>,
+  memory: Option<usize>,
+  emit: Option<(Emit, String)>,
+}
 
-  let args: Vec<String> = args().collect();
+impl Options {
+  fn value<'a>(args: &'a [String], flag: &str) -> Result<Option<&'a str>, DSAsmError> {
+    match args.iter().position(|arg| arg == flag) {
+      Some(i) => match args.get(i + 1) {
+        Some(value) => Ok(Some(value)),
+        None => Err(DSAsmError::Argument(format!("Missing value for {}", flag)))
+      },
+      None => Ok(None)
+    }
+  }
 
-  let fname: &str = if args.len() > 1 {
-    args.get(1).unwrap()
-  } else {
-    return Err(DSAsmError::ArgumentError("Invalid command line arguments".into()).into());
-  };
+  fn number(args: &[String], flag: &str) -> Result<Option<u64>, DSAsmError> {
+    match Options::value(args, flag)? {
+      Some(value) => value.parse().map(Some).map_err(|_| DSAsmError::Argument(format!("Invalid value '{}' for {}, expected a positive integer", value, flag))),
+      None => Ok(None)
+    }
+  }
 
-  let raw = args.contains(&"-raw".to_string());
-  let debug = args.contains(&"-debug".to_string());
-  let content: String = fs::read_to_string(fname).map_err(|e| Err::<File, DSAsmError>(DSAsmError::FileError(format!("{}", e))))?;
+  fn parse(fname: &str, args: &[String]) -> Result<Options, DSAsmError> {
+    let kind = match Options::value(args, "--emit")? {
+      Some("asm") => Some(Emit::Asm),
+      Some("disasm") => Some(Emit::Disasm),
+      Some("dsb") => Some(Emit::Bytecode),
+      Some(kind) => return Err(DSAsmError::Argument(format!("Unsupported --emit kind '{}', expected 'asm', 'disasm' or 'dsb'", kind))),
+      None => None
+    };
+    let eof = match Options::value(args, "--eof")? {
+      Some("unchanged") => EofPolicy::Unchanged,
+      Some("zero") => EofPolicy::Zero,
+      Some("max") => EofPolicy::Max,
+      Some("error") | None => EofPolicy::Error,
+      Some(policy) => return Err(DSAsmError::Argument(format!("Unsupported --eof policy '{}', expected 'unchanged', 'zero', 'max' or 'error'", policy)))
+    };
+    let defaults = Machine::default();
+    let bits = Options::number(args, "--bits")?.map(|bits| u32::try_from(bits).unwrap_or(0));
+    let memory = Options::number(args, "--memory")?.map(|memory| usize::try_from(memory).unwrap_or(usize::MAX));
+    let mut machine = Machine::new(bits.unwrap_or(defaults.bits), defaults.memory);
+    if !machine.has_valid_bits() {
+      return Err(DSAsmError::Argument(format!("Unsupported --bits value {}, expected 8, 16 or 32", machine.bits)));
+    }
+    machine.memory = memory.unwrap_or(machine.memory.min(machine.max_memory()));
+    if !machine.has_valid_memory() {
+      return Err(DSAsmError::Argument(format!("Unsupported --memory value {}, {}-bit cells support between 1 and {} cells", machine.memory, machine.bits, machine.max_memory())));
+    }
+    let emit = match kind {
+      Some(kind) => {
+        let out = match Options::value(args, "-o")? {
+          Some(out) => out.to_string(),
+          None => Path::new(fname).with_extension(if kind == Emit::Bytecode { "dsb" } else { "dsa" }).to_string_lossy().into_owned()
+        };
+        if out == fname {
+          return Err(DSAsmError::Argument(format!("Refusing to overwrite the input file {}, choose another path with -o", fname)));
+        }
+        Some((kind, out))
+      },
+      None => None
+    };
+    Ok(Options {
+      raw: args.contains(&"-raw".to_string()),
+      debug: args.contains(&"-debug".to_string()),
+      bounds: args.contains(&"-bounds".to_string()),
+      strip: args.contains(&"-strip".to_string()),
+      trap: args.contains(&"-trap".to_string()),
+      eof,
+      max_instructions: Options::number(args, "--max-instructions")?,
+      timeout: Options::number(args, "--timeout")?.map(Duration::from_millis),
+      machine,
+      bits,
+      memory,
+      emit
+    })
+  }
+}
 
-  let mut tokenizer: Tokenizer = Tokenizer::new(content.chars().collect());
-  let tokens = if raw {
+fn compile(fname: &str, content: &str, options: &Options) -> Result<Image, DSAsmError> {
+  let debug = options.debug;
+  let mut tokenizer: Tokenizer = Tokenizer::new(content.chars().collect(), fname).with_machine(options.machine);
+  let mut symbols = HashMap::new();
+  let tokens = if options.raw {
     tokenizer.tokenize()
   } else {
     let tokens = tokenizer.tokenize()?;
@@ -28,16 +110,19 @@ fn main() -> Result<(), DSAsmError>{
       println!("\nTOKENS:");
       tokens.iter().for_each(|t| println!("{}", t));
     }
-    let mut parser = Parser::new(tokens);
+    let mut parser = Parser::new(tokens).with_machine(options.machine);
     let nodes = parser.parse_all()?;
     if debug {
       println!("\nNODES:");
       nodes.iter().for_each(|e| println!("{}", e));
     }
-    let mut generator = Generator::new(nodes);
+    let mut generator = Generator::new(nodes).with_machine(options.machine).with_bounds_checks(options.bounds).with_names(parser.symbols().clone());
     let ret = generator.generate_all();
-    println!("\nMEMORY CELLS:");
-    generator.print_memory();
+    symbols = generator.symbols();
+    if debug {
+      println!("\nMEMORY CELLS:");
+      generator.print_memory();
+    }
     ret
   }?;
 
@@ -55,21 +140,90 @@ fn main() -> Result<(), DSAsmError>{
     bytecode.iter().for_each(|ins| println!("{}", ins));
   }
 
-  let mut interpreter: Interpreter = Interpreter::new(bytecode);
+  Ok(Image::new(bytecode, symbols, options.machine))
+}
+
+fn run(image: Image, options: &Options) -> Result<(), DSAsmError> {
+  let debug = options.debug;
+  if let Some((kind, out)) = &options.emit {
+    let bytes = match kind {
+      Emit::Asm => bytecode::to_asm(&image.instructions)?.into_bytes(),
+      Emit::Disasm => Disassembler::new(image.instructions.into_iter().map(|ins| ins.value).collect()).with_symbols(image.symbols).disassemble()?.into_bytes(),
+      Emit::Bytecode => image.to_bytes(!options.strip)
+    };
+    return fs::write(out, bytes).map_err(|e| DSAsmError::File { path: out.clone(), source: e });
+  }
+
+  let mut interpreter: Interpreter = Interpreter::new(image.instructions, io::stdin(), io::stdout()).with_machine(image.machine).with_eof(options.eof)
+    .with_arithmetic(if options.trap { Arithmetic::Trapping } else { Arithmetic::Wrapping });
+  if let Some(max_instructions) = options.max_instructions {
+    interpreter = interpreter.with_max_instructions(max_instructions);
+  }
+  if let Some(timeout) = options.timeout {
+    interpreter = interpreter.with_timeout(timeout);
+  }
 
-  //TODO Methods | Calls and Declaration
   //TODO All the statements
-  //TODO Dereference value assign => *(Expr) = Value
-  //TODO Expression as statement
-  //TODO Think about structs (maybe force them as pointers and add some kind of low level stuff to access nearby fields)
-  //TODO Think about arrays (kinda like structs but change syntax. Maybe let them work the same but just different syntax)
 
-  interpreter.interpret()?;
+  let result = interpreter.interpret();
 
   if debug {
     println!("\nAddress - Value:");
     interpreter.print_memory();
   }
 
-  Ok(())
+  result
+}
+
+fn main() -> ExitCode {
+
+  let args: Vec<String> = args().collect();
+
+  let fname: &str = if args.len() > 1 {
+    args.get(1).unwrap()
+  } else {
+    Diagnostic::from(&DSAsmError::Argument("Invalid command line arguments".into())).emit("");
+    return ExitCode::FAILURE;
+  };
+
+  let options = match Options::parse(fname, &args) {
+    Ok(options) => options,
+    Err(e) => {
+      Diagnostic::from(&e).emit("");
+      return ExitCode::FAILURE;
+    }
+  };
+  let bytes = match fs::read(fname) {
+    Ok(bytes) => bytes,
+    Err(e) => {
+      Diagnostic::from(&DSAsmError::File { path: fname.to_string(), source: e }).emit("");
+      return ExitCode::FAILURE;
+    }
+  };
+  let (content, image) = if bytes.starts_with(&Image::MAGIC) {
+    (String::new(), Image::from_bytes(&bytes).and_then(|image| image.check_machine(options.bits, options.memory).map(|_| image)))
+  } else {
+    match String::from_utf8(bytes) {
+      Ok(content) => {
+        let image = compile(fname, &content, &options);
+        (content, image)
+      },
+      Err(e) => {
+        Diagnostic::from(&DSAsmError::File { path: fname.to_string(), source: io::Error::new(io::ErrorKind::InvalidData, e) }).emit("");
+        return ExitCode::FAILURE;
+      }
+    }
+  };
+
+  match image.and_then(|image| run(image, &options)) {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(e) => {
+      let source = match e.span() {
+        Some(span) if content.is_empty() => fs::read_to_string(&*span.file).unwrap_or_default(),
+        _ => content
+      };
+      diagnostics::report(&e, &source);
+      ExitCode::FAILURE
+    }
+  }
 }